@@ -2,6 +2,9 @@
 set -e
 pushd "$(dirname $0)"
 
+# The sim tests only run MVP wasm, so the contracts are built with a toolchain that doesn't
+# enable the newer wasm features by default, for example Rust 1.69.
+
 # Removing rlib for contract building
 perl -i -pe 's/\["cdylib", "rlib"\]/\["cdylib"\]/' Cargo.toml
 
@@ -12,3 +15,15 @@ cp target/wasm32-unknown-unknown/release/ft_lockup.wasm ./res/
 # Restoring rlib
 perl -i -pe 's/\["cdylib"\]/\["cdylib", "rlib"\]/' Cargo.toml
 popd
+
+# Building the mock staking contract for the sim tests
+pushd "$(dirname $0)/tests/mock-farm"
+RUSTFLAGS='-C link-arg=-s' cargo build --target wasm32-unknown-unknown --release
+cp target/wasm32-unknown-unknown/release/mock_farm.wasm ../../res/
+popd
+
+# Building the mock beneficiary contract for the sim tests
+pushd "$(dirname $0)/tests/mock-claimer"
+RUSTFLAGS='-C link-arg=-s' cargo build --target wasm32-unknown-unknown --release
+cp target/wasm32-unknown-unknown/release/mock_claimer.wasm ../../res/
+popd
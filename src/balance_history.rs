@@ -0,0 +1,141 @@
+use crate::*;
+use std::convert::TryInto;
+
+/// The account's totals of a single token after a change.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Deserialize))]
+pub struct BalanceSnapshot {
+    pub timestamp: TimestampSec,
+    /// The balance held in the account's lockups, that isn't claimed or terminated yet.
+    #[serde(with = "u128_dec_format")]
+    pub locked_balance: Balance,
+    /// The total balance claimed by the account.
+    #[serde(with = "u128_dec_format")]
+    pub claimed_balance: Balance,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns the account's locked balance of the token as of the given timestamp.
+    pub fn get_locked_balance_at(
+        &self,
+        account_id: ValidAccountId,
+        timestamp: TimestampSec,
+        token_account_id: TokenAccountId,
+    ) -> WrappedBalance {
+        self.get_balance_snapshot_at(account_id, timestamp, token_account_id)
+            .map(|snapshot| snapshot.locked_balance)
+            .unwrap_or(0)
+            .into()
+    }
+
+    /// Returns the latest snapshot of the account's totals of the token at or before the
+    /// given timestamp.
+    pub fn get_balance_snapshot_at(
+        &self,
+        account_id: ValidAccountId,
+        timestamp: TimestampSec,
+        token_account_id: TokenAccountId,
+    ) -> Option<BalanceSnapshot> {
+        let account_id: AccountId = account_id.into();
+        let history = match self
+            .balance_history
+            .get(&(account_id.clone(), token_account_id.clone()))
+        {
+            Some(history) => history,
+            None => {
+                return self.internal_get_legacy_balance_snapshot_at(
+                    &account_id,
+                    timestamp,
+                    &token_account_id,
+                )
+            }
+        };
+        // Binary search for the first snapshot after the timestamp.
+        let (mut low, mut high) = (0, history.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if history.get(mid).unwrap().timestamp <= timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        if low == 0 {
+            None
+        } else {
+            history.get(low - 1)
+        }
+    }
+}
+
+impl Contract {
+    /// Returns the account's balance history of the token, or a new empty one.
+    pub(crate) fn internal_get_balance_history(
+        &self,
+        account_id: &AccountId,
+        token_account_id: &TokenAccountId,
+    ) -> Vector<BalanceSnapshot> {
+        self.balance_history
+            .get(&(account_id.clone(), token_account_id.clone()))
+            .unwrap_or_else(|| {
+                Vector::new(StorageKey::BalanceHistory {
+                    account_token_hash: env::sha256(
+                        &(account_id.clone(), token_account_id.clone())
+                            .try_to_vec()
+                            .unwrap(),
+                    )
+                    .try_into()
+                    .unwrap(),
+                })
+            })
+    }
+
+    /// The history of an account, that isn't migrated yet, starts with the totals of its
+    /// legacy lockups as of the upgrade.
+    fn internal_get_legacy_balance_snapshot_at(
+        &self,
+        account_id: &AccountId,
+        timestamp: TimestampSec,
+        token_account_id: &TokenAccountId,
+    ) -> Option<BalanceSnapshot> {
+        if &self.legacy_lockups.as_ref()?.token_account_id != token_account_id {
+            return None;
+        }
+        let indices = self.legacy_account_lockups.as_ref()?.get(account_id)?;
+        self.internal_legacy_balance_snapshot(&indices)
+            .filter(|snapshot| snapshot.timestamp <= timestamp)
+    }
+
+    /// Records a new snapshot of the account's totals of the token, updated by `update`.
+    /// Changes within the same second replace the last snapshot.
+    pub(crate) fn internal_update_balance_history<F>(
+        &mut self,
+        account_id: &AccountId,
+        token_account_id: &TokenAccountId,
+        update: F,
+    ) where
+        F: FnOnce(&mut BalanceSnapshot),
+    {
+        // The history of a legacy account is seeded first.
+        self.internal_migrate_account_lockups(account_id);
+        let mut history = self.internal_get_balance_history(account_id, token_account_id);
+        let timestamp = current_timestamp_sec();
+        let mut snapshot = if history.is_empty() {
+            BalanceSnapshot::default()
+        } else {
+            history.get(history.len() - 1).unwrap()
+        };
+        let replace = !history.is_empty() && snapshot.timestamp == timestamp;
+        snapshot.timestamp = timestamp;
+        update(&mut snapshot);
+        if replace {
+            history.replace(history.len() - 1, &snapshot);
+        } else {
+            history.push(&snapshot);
+        }
+        self.balance_history
+            .insert(&(account_id.clone(), token_account_id.clone()), &history);
+    }
+}
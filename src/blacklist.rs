@@ -0,0 +1,233 @@
+use crate::*;
+
+pub const MAX_BLACKLIST_REASON_LENGTH: usize = 256;
+
+pub const DEFAULT_BLACKLIST_MESSAGE: &str = "Your wallet is facing issues with the tokens claim. To claim your tokens contact us via hq@pembrock.finance";
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Deserialize))]
+pub struct BlacklistEntry {
+    /// The reason why the account was blacklisted.
+    pub reason: String,
+    /// The timestamp when the account was blacklisted.
+    pub blacklisted_at: TimestampSec,
+    /// An optional timestamp after which the account is no longer blacklisted.
+    pub expires_at: Option<TimestampSec>,
+    /// If true, the account's lockups don't unlock while the account is blacklisted.
+    /// Once the account is removed from the blacklist (or the entry expires), the remaining
+    /// part of every account's lockup schedule is delayed by the blacklisted duration.
+    pub freeze: bool,
+}
+
+impl BlacklistEntry {
+    pub fn is_active(&self, timestamp: TimestampSec) -> bool {
+        self.expires_at
+            .map(|expires_at| timestamp < expires_at)
+            .unwrap_or(true)
+    }
+
+    /// Returns the time the lockups were frozen for, up to the given timestamp.
+    pub fn frozen_duration(&self, timestamp: TimestampSec) -> TimestampSec {
+        if !self.freeze {
+            return 0;
+        }
+        let end = self
+            .expires_at
+            .map(|expires_at| std::cmp::min(expires_at, timestamp))
+            .unwrap_or(timestamp);
+        end.saturating_sub(self.blacklisted_at)
+    }
+}
+
+/// A lifted freeze of the account's lockups. The lockups are delayed by it lazily, once they
+/// are loaded.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LiftedFreeze {
+    /// The timestamp the account was blacklisted at.
+    pub from: TimestampSec,
+    pub duration: TimestampSec,
+}
+
+impl Lockup {
+    /// Delays the lockup schedule (and the inline vesting schedule) by the frozen duration.
+    /// A hashed vesting schedule can't be delayed, so it keeps vesting while frozen.
+    pub fn apply_freeze(&mut self, entry: &BlacklistEntry, timestamp: TimestampSec) {
+        self.delay(entry.blacklisted_at, entry.frozen_duration(timestamp));
+    }
+
+    /// Delays the schedule by the account's lifted freezes, that it isn't delayed by yet.
+    pub fn apply_lifted_freezes(&mut self, freezes: &[LiftedFreeze]) {
+        for freeze in freezes.iter().skip(self.num_applied_freezes as usize) {
+            self.delay(freeze.from, freeze.duration);
+        }
+        self.num_applied_freezes = freezes.len() as u32;
+    }
+
+    fn delay(&mut self, from: TimestampSec, duration: TimestampSec) {
+        if duration == 0 {
+            return;
+        }
+        self.schedule.shift(from, duration);
+        self.schedule_id = None;
+        if let Some(TerminationConfig {
+            vesting_schedule: Some(HashOrSchedule::Schedule(schedule)),
+            ..
+        }) = &mut self.termination_config
+        {
+            schedule.shift(from, duration);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Blacklists the given account ID, so it can't claim.
+    /// If `freeze` is true, the account's lockups also stop unlocking until the account is
+    /// removed from the blacklist. Note, lockups created while the account is frozen are
+    /// delayed as well.
+    /// If the account is already blacklisted, the previous entry is lifted first.
+    #[payable]
+    pub fn add_to_blacklist(
+        &mut self,
+        account_id: ValidAccountId,
+        reason: Option<String>,
+        expires_at: Option<TimestampSec>,
+        freeze: Option<bool>,
+    ) {
+        assert_one_yocto();
+        self.assert_owner();
+        let reason = reason.unwrap_or_default();
+        assert!(
+            reason.len() <= MAX_BLACKLIST_REASON_LENGTH,
+            "The reason is too long"
+        );
+        let blacklisted_at = current_timestamp_sec();
+        if let Some(expires_at) = expires_at {
+            assert!(
+                expires_at > blacklisted_at,
+                "The expiration should be in the future"
+            );
+        }
+        let account_id: AccountId = account_id.into();
+        self.internal_lift_blacklist(&account_id);
+        let entry = BlacklistEntry {
+            reason,
+            blacklisted_at,
+            expires_at,
+            freeze: freeze.unwrap_or(false),
+        };
+        self.blacklist.insert(&account_id, &entry);
+        event::emit(Event::Blacklisted {
+            account_id,
+            reason: entry.reason,
+            expires_at: entry.expires_at,
+            freeze: entry.freeze,
+        });
+    }
+
+    #[payable]
+    pub fn remove_to_blacklist(&mut self, account_id: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        assert!(
+            self.internal_lift_blacklist(account_id.as_ref()),
+            "Not blacklisted"
+        );
+    }
+
+    #[payable]
+    pub fn set_blacklist_message(&mut self, message: String) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.blacklist_message = message;
+    }
+
+    pub fn get_blacklist_entry(&self, account_id: ValidAccountId) -> Option<BlacklistEntry> {
+        self.blacklist.get(account_id.as_ref())
+    }
+
+    pub fn get_blacklist_message(&self) -> String {
+        self.blacklist_message.clone()
+    }
+}
+
+impl Contract {
+    /// Panics with the blacklist message if the account is blacklisted.
+    pub(crate) fn assert_not_blacklisted(&mut self, account_id: &AccountId) {
+        if self.internal_is_blacklisted(account_id) {
+            env::panic(self.blacklist_message.as_bytes());
+        }
+    }
+
+    /// Returns true if the account is blacklisted. Lifts the expired entry first.
+    pub(crate) fn internal_is_blacklisted(&mut self, account_id: &AccountId) -> bool {
+        self.internal_lift_expired_blacklist(account_id);
+        self.blacklist.get(account_id).is_some()
+    }
+
+    /// Lifts the account's blacklist entry, if it has expired.
+    pub(crate) fn internal_lift_expired_blacklist(&mut self, account_id: &AccountId) {
+        if let Some(entry) = self.blacklist.get(account_id) {
+            if !entry.is_active(current_timestamp_sec()) {
+                self.internal_lift_blacklist(account_id);
+            }
+        }
+    }
+
+    /// Removes the account from the blacklist and records the frozen duration, that the
+    /// account's lockups are delayed by once they are loaded. Returns false if the account
+    /// wasn't blacklisted.
+    pub(crate) fn internal_lift_blacklist(&mut self, account_id: &AccountId) -> bool {
+        let entry = match self.blacklist.remove(account_id) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let frozen_duration = entry.frozen_duration(current_timestamp_sec());
+        if frozen_duration > 0 {
+            let mut freezes = self.internal_get_lifted_freezes(account_id);
+            freezes.push(LiftedFreeze {
+                from: entry.blacklisted_at,
+                duration: frozen_duration,
+            });
+            self.lifted_freezes.insert(account_id, &freezes);
+        }
+        event::emit(Event::BlacklistLifted {
+            account_id: account_id.clone(),
+            frozen_duration,
+        });
+        true
+    }
+
+    pub(crate) fn internal_get_lifted_freezes(&self, account_id: &AccountId) -> Vec<LiftedFreeze> {
+        self.lifted_freezes.get(account_id).unwrap_or_default()
+    }
+
+    /// Delays the lockup by the beneficiary's active freeze so far, the way the views show it.
+    /// The lockup shouldn't be saved afterwards, since the freeze is applied again once it's
+    /// lifted.
+    pub(crate) fn internal_apply_active_freeze(&self, lockup: &mut Lockup) {
+        if let Some(entry) = self.blacklist.get(lockup.account_id.as_ref()) {
+            lockup.apply_freeze(&entry, current_timestamp_sec());
+        }
+    }
+
+    /// Returns the lockup by index, delayed by the beneficiary's active freeze so far.
+    pub(crate) fn internal_unwrap_frozen_lockup(&self, lockup_index: LockupIndex) -> Lockup {
+        let mut lockup = self.internal_unwrap_lockup(lockup_index);
+        self.internal_apply_active_freeze(&mut lockup);
+        lockup
+    }
+
+    /// Returns the timestamp the account's lockups are vested at for the termination.
+    /// The vesting doesn't progress while the account is frozen.
+    pub(crate) fn internal_vesting_timestamp(&self, account_id: &AccountId) -> TimestampSec {
+        let timestamp = current_timestamp_sec();
+        match self.blacklist.get(account_id) {
+            Some(entry) if entry.freeze && entry.is_active(timestamp) => {
+                std::cmp::min(entry.blacklisted_at, timestamp)
+            }
+            _ => timestamp,
+        }
+    }
+}
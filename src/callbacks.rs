@@ -1,95 +1,367 @@
 use crate::*;
+use near_sdk::PromiseResult;
+use std::convert::TryInto;
 
-trait SelfCallbacks {
+pub trait SelfCallbacks {
     fn after_ft_transfer(
         &mut self,
         account_id: AccountId,
-        lockup_claims: Vec<LockupClaim>,
+        token_claims: Vec<TokenClaims>,
+        keeper_id: Option<AccountId>,
     ) -> WrappedBalance;
 
+    fn after_storage_balance_check(
+        &mut self,
+        claim_request: ClaimRequest,
+        token_account_id: TokenAccountId,
+        storage_deposit: WrappedBalance,
+    ) -> PromiseOrValue<WrappedBalance>;
+
+    fn after_token_registration_check(
+        &mut self,
+        claim_request: ClaimRequest,
+        token_ids: Vec<TokenAccountId>,
+        storage_deposit: WrappedBalance,
+    ) -> PromiseOrValue<WrappedBalance>;
+
     fn after_lockup_termination(
         &mut self,
         account_id: AccountId,
+        token_account_id: TokenAccountId,
+        amount: WrappedBalance,
+    ) -> WrappedBalance;
+
+    fn after_lockup_created_notification(
+        &mut self,
+        lockup_index: LockupIndex,
+        account_id: AccountId,
+    );
+
+    fn after_stake(&mut self, lockup_index: LockupIndex, amount: WrappedBalance) -> WrappedBalance;
+
+    fn after_unstake(
+        &mut self,
+        lockup_index: LockupIndex,
         amount: WrappedBalance,
     ) -> WrappedBalance;
 }
 
 #[near_bindgen]
 impl SelfCallbacks for Contract {
+    /// Resolves the claim transfers. The promise results are in the order of `token_claims`,
+    /// and every token is resolved independently. The failed transfers are credited to the
+    /// withdrawable balance, unless the rollback of the failed claims is enabled.
+    /// The keeper fees are only paid for the successful transfers.
     #[private]
     fn after_ft_transfer(
         &mut self,
         account_id: AccountId,
-        lockup_claims: Vec<LockupClaim>,
+        token_claims: Vec<TokenClaims>,
+        keeper_id: Option<AccountId>,
     ) -> WrappedBalance {
-        let promise_success = is_promise_success();
+        self.internal_unlock_claims(&account_id);
         let mut total_balance = 0;
-        if promise_success {
-            let mut remove_indices = vec![];
-            for LockupClaim {
-                index,
-                is_final,
-                unclaimed_balance,
-            } in lockup_claims
-            {
-                if is_final {
-                    remove_indices.push(index);
+        for (i, claims) in token_claims.into_iter().enumerate() {
+            let promise_success =
+                matches!(env::promise_result(i as _), PromiseResult::Successful(_));
+            if promise_success {
+                total_balance += self.internal_finalize_claims(&account_id, claims.lockup_claims)
+                    + claims.pending_balance
+                    - claims.keeper_fee;
+                if let Some(keeper_id) = &keeper_id {
+                    self.internal_credit_withdrawable(
+                        keeper_id,
+                        &claims.token_account_id,
+                        claims.keeper_fee,
+                    );
                 }
-                total_balance += unclaimed_balance.0;
-            }
-            if !remove_indices.is_empty() {
-                let mut indices = self.account_lockups.get(&account_id).unwrap_or_default();
-                for index in remove_indices {
-                    indices.remove(&index);
+            } else {
+                self.internal_refund_near(&claims.token_account_id, claims.transfer_amount());
+                self.internal_set_token_registered(&account_id, &claims.token_account_id, false);
+                if self.rollback_failed_claims {
+                    log!(
+                        "Token transfer of {} has failed. Refunding.",
+                        claims.token_account_id
+                    );
+                    self.internal_revert_claims(
+                        &account_id,
+                        &claims.token_account_id,
+                        claims.lockup_claims,
+                    );
+                    self.internal_credit_withdrawable(
+                        &account_id,
+                        &claims.token_account_id,
+                        claims.pending_balance,
+                    );
+                } else {
+                    log!("Token transfer of {} has failed.", claims.token_account_id);
+                    let amount = self.internal_finalize_claims(&account_id, claims.lockup_claims)
+                        + claims.pending_balance;
+                    self.internal_credit_withdrawable(
+                        &account_id,
+                        &claims.token_account_id,
+                        amount,
+                    );
                 }
-                self.internal_save_account_lockups(&account_id, indices);
             }
-        } else {
-            log!("Token transfer has failed. Refunding.");
-            let mut modified = false;
-            let mut indices = self.account_lockups.get(&account_id).unwrap_or_default();
-            for LockupClaim {
-                index,
-                unclaimed_balance,
-                ..
-            } in lockup_claims
-            {
-                if indices.insert(index) {
-                    modified = true;
+        }
+        total_balance.into()
+    }
+
+    /// Registers the account with the token, unless it's already registered, using only the
+    /// minimum storage balance. The rest of the attached deposit is refunded to the account.
+    #[private]
+    fn after_storage_balance_check(
+        &mut self,
+        claim_request: ClaimRequest,
+        token_account_id: TokenAccountId,
+        storage_deposit: WrappedBalance,
+    ) -> PromiseOrValue<WrappedBalance> {
+        let account_id = claim_request.account_id.clone();
+        let storage_balance = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                serde_json::from_slice::<serde_json::Value>(&value).ok()
+            }
+            _ => None,
+        };
+        let storage_bounds = match env::promise_result(1) {
+            PromiseResult::Successful(value) => {
+                serde_json::from_slice::<TokenStorageBounds>(&value).ok()
+            }
+            _ => None,
+        };
+        match (storage_balance, storage_bounds) {
+            (Some(storage_balance), _) if !storage_balance.is_null() => {
+                self.internal_set_token_registered(&account_id, &token_account_id, true);
+                log!(
+                    "{} is already registered with {}. Refunding {}",
+                    account_id,
+                    token_account_id,
+                    storage_deposit.0
+                );
+                Promise::new(account_id).transfer(storage_deposit.0);
+                let ClaimRequest {
+                    account_id,
+                    token_account_id,
+                    keeper_fee,
+                } = claim_request;
+                self.internal_claim(account_id, token_account_id, keeper_fee)
+            }
+            (Some(_), Some(storage_bounds)) if storage_bounds.min.0 <= storage_deposit.0 => {
+                let refund = storage_deposit.0 - storage_bounds.min.0;
+                if refund > 0 {
+                    log!("Refunding {} of the storage deposit", refund);
+                    Promise::new(account_id.clone()).transfer(refund);
                 }
-                let mut lockup = self.lockups.get(index as _).unwrap();
-                lockup.claimed_balance -= unclaimed_balance.0;
-                self.lockups.replace(index as _, &lockup);
+                let claim_gas = remaining_gas(GAS_FOR_STORAGE_DEPOSIT + GAS_FOR_CALL_SCHEDULING);
+                ext_token_storage::storage_deposit(
+                    Some(account_id.try_into().unwrap()),
+                    Some(true),
+                    &token_account_id,
+                    storage_bounds.min.0,
+                    GAS_FOR_STORAGE_DEPOSIT,
+                )
+                .then(ext_self::after_token_registration_check(
+                    claim_request,
+                    vec![token_account_id],
+                    storage_bounds.min,
+                    &env::current_account_id(),
+                    NO_DEPOSIT,
+                    claim_gas,
+                ))
+                .into()
+            }
+            _ => {
+                log!(
+                    "Can't register {} with {}. Refunding {}",
+                    account_id,
+                    token_account_id,
+                    storage_deposit.0
+                );
+                Promise::new(account_id).transfer(storage_deposit.0);
+                PromiseOrValue::Value(0.into())
             }
+        }
+    }
 
-            if modified {
-                self.internal_save_account_lockups(&account_id, indices);
+    /// Claims once the account is registered with all the claimed tokens. The promise results
+    /// are in the order of `token_ids`. If the account isn't registered with some token,
+    /// nothing is claimed, and the storage deposit, if any, is refunded to the account.
+    #[private]
+    fn after_token_registration_check(
+        &mut self,
+        claim_request: ClaimRequest,
+        token_ids: Vec<TokenAccountId>,
+        storage_deposit: WrappedBalance,
+    ) -> PromiseOrValue<WrappedBalance> {
+        let ClaimRequest {
+            account_id,
+            token_account_id,
+            keeper_fee,
+        } = claim_request;
+        for (i, token_id) in token_ids.iter().enumerate() {
+            let is_registered = match env::promise_result(i as _) {
+                PromiseResult::Successful(value) => {
+                    serde_json::from_slice::<serde_json::Value>(&value)
+                        .map(|storage_balance| !storage_balance.is_null())
+                        .unwrap_or(false)
+                }
+                _ => false,
+            };
+            if !is_registered {
+                if storage_deposit.0 > 0 {
+                    log!(
+                        "Storage deposit on {} has failed. Refunding {}",
+                        token_id,
+                        storage_deposit.0
+                    );
+                    Promise::new(account_id).transfer(storage_deposit.0);
+                } else {
+                    log!("{} is not registered with {}", account_id, token_id);
+                }
+                return PromiseOrValue::Value(0.into());
             }
+            self.internal_set_token_registered(&account_id, token_id, true);
         }
-        total_balance.into()
+        self.internal_claim(account_id, token_account_id, keeper_fee)
     }
 
     #[private]
     fn after_lockup_termination(
         &mut self,
         account_id: AccountId,
+        token_account_id: TokenAccountId,
         amount: WrappedBalance,
     ) -> WrappedBalance {
         let promise_success = is_promise_success();
         if !promise_success {
             log!("Lockup termination transfer has failed.");
+            self.internal_refund_near(&token_account_id, amount.0);
             // There is no internal balance, so instead we create a new lockup.
-            let lockup = Lockup::new_unlocked(account_id, amount.0);
-            let lockup_index = self.internal_add_lockup(&lockup);
+            let mut lockup = Lockup::new_unlocked(account_id.clone(), token_account_id, amount.0);
+            lockup.creator_id = Some(env::current_account_id());
+            lockup.created_at = Some(current_timestamp_sec());
+            let lockup_index = self.internal_add_lockup(lockup);
             log!(
                 "Generated a new lockup #{} as a refund of {} for account {}",
                 lockup_index,
                 amount.0,
-                lockup.account_id.as_ref(),
+                account_id,
             );
             0.into()
         } else {
             amount
         }
     }
+
+    #[private]
+    fn after_lockup_created_notification(
+        &mut self,
+        lockup_index: LockupIndex,
+        account_id: AccountId,
+    ) {
+        if !is_promise_success() {
+            log!(
+                "Failed to notify {} about the lockup #{}",
+                account_id,
+                lockup_index
+            );
+        }
+    }
+
+    /// Releases the part of the stake that was refunded by the farm. Returns the staked amount.
+    #[private]
+    fn after_stake(&mut self, lockup_index: LockupIndex, amount: WrappedBalance) -> WrappedBalance {
+        let used_amount = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value)
+                .map(|used_amount| std::cmp::min(used_amount.0, amount.0))
+                .unwrap_or(amount.0),
+            _ => 0,
+        };
+        if used_amount < amount.0 {
+            log!(
+                "Staking of lockup #{} has refunded {}",
+                lockup_index,
+                amount.0 - used_amount
+            );
+            self.internal_release_stake(lockup_index, amount.0 - used_amount);
+        }
+        used_amount.into()
+    }
+
+    #[private]
+    fn after_unstake(
+        &mut self,
+        lockup_index: LockupIndex,
+        amount: WrappedBalance,
+    ) -> WrappedBalance {
+        if is_promise_success() {
+            self.internal_release_stake(lockup_index, amount.0);
+            amount
+        } else {
+            log!("Unstaking of lockup #{} has failed", lockup_index);
+            0.into()
+        }
+    }
+}
+
+impl Contract {
+    /// The NEAR of a failed transfer is refunded to the contract, so it's held again.
+    fn internal_refund_near(&mut self, token_account_id: &TokenAccountId, amount: Balance) {
+        if token_account_id == NEAR_TOKEN_ID {
+            self.near_locked_balance += amount;
+        }
+    }
+
+    /// Removes the fully claimed lockups after a successful transfer.
+    /// Returns the transferred balance.
+    fn internal_finalize_claims(
+        &mut self,
+        account_id: &AccountId,
+        lockup_claims: Vec<LockupClaim>,
+    ) -> Balance {
+        let mut total_balance = 0;
+        let mut remove_indices = vec![];
+        for LockupClaim {
+            index,
+            is_final,
+            unclaimed_balance,
+        } in lockup_claims
+        {
+            if is_final {
+                remove_indices.push(index);
+            }
+            total_balance += unclaimed_balance.0;
+        }
+        self.internal_remove_finished_lockups(account_id, remove_indices);
+        total_balance
+    }
+
+    /// Rolls back the claimed balances after a failed transfer.
+    fn internal_revert_claims(
+        &mut self,
+        account_id: &AccountId,
+        token_account_id: &TokenAccountId,
+        lockup_claims: Vec<LockupClaim>,
+    ) {
+        let mut indices = vec![];
+        let mut total_balance = 0;
+        for LockupClaim {
+            index,
+            unclaimed_balance,
+            ..
+        } in lockup_claims
+        {
+            indices.push(index);
+            total_balance += unclaimed_balance.0;
+            let mut lockup = self.internal_unwrap_lockup(index);
+            lockup.claimed_balance -= unclaimed_balance.0;
+            self.internal_save_lockup(index, lockup);
+        }
+        self.internal_insert_account_lockups(account_id, &indices);
+        self.internal_update_balance_history(account_id, token_account_id, |snapshot| {
+            snapshot.locked_balance += total_balance;
+            snapshot.claimed_balance -= total_balance;
+        });
+    }
 }
@@ -0,0 +1,266 @@
+use crate::*;
+use std::convert::TryInto;
+
+pub type CampaignIndex = u32;
+
+/// A pool of lockups, that are only created once their beneficiaries show up with a proof.
+/// The leaves of the Merkle tree are `sha256(borsh((account_id, schedule)))`, and the inner
+/// nodes are the hashes of the sorted pair of children.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Campaign {
+    pub creator_id: AccountId,
+    pub token_account_id: TokenAccountId,
+    pub merkle_root: CryptoHash,
+    pub total_balance: Balance,
+    /// The balance of the created lockups and the refund.
+    pub claimed_balance: Balance,
+    /// The timestamp after which the lockups can't be created, and the remaining balance can
+    /// be refunded to the creator.
+    pub expires_at: TimestampSec,
+    /// The category of the created lockups.
+    pub category: Option<String>,
+    pub refunded: bool,
+}
+
+/// The deposit message that funds a new campaign.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+pub struct NewCampaign {
+    pub merkle_root: Base58CryptoHash,
+    pub expires_at: TimestampSec,
+    pub category: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Deserialize))]
+pub struct CampaignView {
+    pub creator_id: AccountId,
+    pub token_account_id: TokenAccountId,
+    pub merkle_root: Base58CryptoHash,
+    #[serde(with = "u128_dec_format")]
+    pub total_balance: Balance,
+    #[serde(with = "u128_dec_format")]
+    pub claimed_balance: Balance,
+    pub expires_at: TimestampSec,
+    pub category: Option<String>,
+    pub refunded: bool,
+}
+
+impl From<Campaign> for CampaignView {
+    fn from(campaign: Campaign) -> Self {
+        Self {
+            creator_id: campaign.creator_id,
+            token_account_id: campaign.token_account_id,
+            merkle_root: campaign.merkle_root.into(),
+            total_balance: campaign.total_balance,
+            claimed_balance: campaign.claimed_balance,
+            expires_at: campaign.expires_at,
+            category: campaign.category,
+            refunded: campaign.refunded,
+        }
+    }
+}
+
+pub fn campaign_leaf_hash(account_id: &AccountId, schedule: &Schedule) -> CryptoHash {
+    let mut res = CryptoHash::default();
+    res.copy_from_slice(&env::sha256(&(account_id, schedule).try_to_vec().unwrap()));
+    res
+}
+
+/// Returns the Merkle root computed from the leaf and the proof.
+pub fn merkle_root(leaf: CryptoHash, proof: &[Base58CryptoHash]) -> CryptoHash {
+    proof.iter().fold(leaf, |node, sibling| {
+        let sibling: CryptoHash = (*sibling).into();
+        let (left, right) = if node <= sibling {
+            (node, sibling)
+        } else {
+            (sibling, node)
+        };
+        let mut res = CryptoHash::default();
+        res.copy_from_slice(&env::sha256(&[left, right].concat()));
+        res
+    })
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Creates the beneficiary's lockup from the campaign, given the Merkle proof of the
+    /// (account, schedule) leaf. The storage of the lockup is charged to the beneficiary's
+    /// prepaid storage balance.
+    pub fn claim_campaign_lockup(
+        &mut self,
+        campaign_index: CampaignIndex,
+        schedule: Schedule,
+        proof: Vec<Base58CryptoHash>,
+    ) -> LockupIndex {
+        self.assert_not_paused(PauseKind::Deposit);
+        let account_id = env::predecessor_account_id();
+        self.assert_not_blacklisted(&account_id);
+        let mut campaign = self
+            .campaigns
+            .get(campaign_index as _)
+            .expect("Campaign not found");
+        assert!(
+            current_timestamp_sec() < campaign.expires_at,
+            "The campaign has expired"
+        );
+        let leaf = campaign_leaf_hash(&account_id, &schedule);
+        assert_eq!(
+            merkle_root(leaf, &proof),
+            campaign.merkle_root,
+            "Invalid proof"
+        );
+        // The claim record is charged to the beneficiary with the lockup.
+        let initial_storage_usage = env::storage_usage();
+        assert!(
+            self.campaign_claims.insert(&(campaign_index, leaf)),
+            "Already claimed"
+        );
+        let total_balance = schedule.total_balance();
+        campaign.claimed_balance += total_balance;
+        assert!(
+            campaign.claimed_balance <= campaign.total_balance,
+            "Not enough balance in the campaign"
+        );
+        let timestamp = current_timestamp_sec();
+        let lockup = Lockup {
+            account_id: account_id.clone().try_into().unwrap(),
+            token_account_id: campaign.token_account_id.clone(),
+            schedule,
+            schedule_id: None,
+            claimed_balance: 0,
+            termination_config: None,
+            creator_id: Some(campaign.creator_id.clone()),
+            created_at: Some(timestamp),
+            memo: Some(format!("Campaign #{}", campaign_index)),
+            category: campaign.category.clone(),
+            // The campaign lockups can't be cancelled.
+            cancellation_deadline: Some(0),
+            claim_deadline: None,
+            num_applied_freezes: 0,
+        };
+        lockup.assert_new_valid(total_balance);
+        self.campaigns.replace(campaign_index as _, &campaign);
+        let index = self.internal_add_lockup(lockup);
+        self.internal_charge_lockup_storage(&account_id, index, initial_storage_usage);
+        log!(
+            "Created new lockup for {} with index {} from campaign #{}",
+            account_id,
+            index,
+            campaign_index
+        );
+        index
+    }
+
+    /// Refunds the remaining balance of the expired campaign to its creator.
+    pub fn refund_campaign(
+        &mut self,
+        campaign_index: CampaignIndex,
+    ) -> PromiseOrValue<WrappedBalance> {
+        self.assert_not_paused(PauseKind::Terminate);
+        let mut campaign = self
+            .campaigns
+            .get(campaign_index as _)
+            .expect("Campaign not found");
+        assert!(
+            current_timestamp_sec() >= campaign.expires_at,
+            "The campaign hasn't expired"
+        );
+        assert!(!campaign.refunded, "Already refunded");
+        let amount = campaign.total_balance - campaign.claimed_balance;
+        campaign.claimed_balance = campaign.total_balance;
+        campaign.refunded = true;
+        self.campaigns.replace(campaign_index as _, &campaign);
+        if amount > 0 {
+            self.internal_transfer(
+                &campaign.token_account_id,
+                campaign.creator_id.clone(),
+                amount,
+                format!("Refund of the campaign #{}", campaign_index),
+            )
+            .then(ext_self::after_lockup_termination(
+                campaign.creator_id,
+                campaign.token_account_id,
+                amount.into(),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_AFTER_FT_TRANSFER,
+            ))
+            .into()
+        } else {
+            PromiseOrValue::Value(0.into())
+        }
+    }
+
+    pub fn get_campaign(&self, campaign_index: CampaignIndex) -> Option<CampaignView> {
+        self.campaigns
+            .get(campaign_index as _)
+            .map(|campaign| campaign.into())
+    }
+
+    pub fn get_num_campaigns(&self) -> u32 {
+        self.campaigns.len() as _
+    }
+
+    pub fn is_campaign_lockup_claimed(
+        &self,
+        campaign_index: CampaignIndex,
+        account_id: ValidAccountId,
+        schedule: Schedule,
+    ) -> bool {
+        let leaf = campaign_leaf_hash(account_id.as_ref(), &schedule);
+        self.campaign_claims.contains(&(campaign_index, leaf))
+    }
+
+    pub fn hash_campaign_leaf(
+        &self,
+        account_id: ValidAccountId,
+        schedule: Schedule,
+    ) -> Base58CryptoHash {
+        campaign_leaf_hash(account_id.as_ref(), &schedule).into()
+    }
+}
+
+impl Contract {
+    /// Adds the campaign funded by the depositor. The campaign storage is charged to the
+    /// depositor's prepaid storage balance.
+    pub(crate) fn internal_create_campaign(
+        &mut self,
+        depositor_id: &AccountId,
+        token_account_id: TokenAccountId,
+        new_campaign: NewCampaign,
+        amount: Balance,
+    ) -> CampaignIndex {
+        assert!(amount > 0, "The campaign balance should be positive");
+        assert!(
+            new_campaign.expires_at > current_timestamp_sec(),
+            "The expiration should be in the future"
+        );
+        if let Some(category) = &new_campaign.category {
+            assert!(
+                category.len() <= MAX_LOCKUP_CATEGORY_LENGTH,
+                "The category is too long"
+            );
+        }
+        let initial_storage_usage = env::storage_usage();
+        let index = self.campaigns.len() as CampaignIndex;
+        self.campaigns.push(&Campaign {
+            creator_id: depositor_id.clone(),
+            token_account_id,
+            merkle_root: new_campaign.merkle_root.into(),
+            total_balance: amount,
+            claimed_balance: 0,
+            expires_at: new_campaign.expires_at,
+            category: new_campaign.category,
+            refunded: false,
+        });
+        self.internal_charge_account_storage(
+            depositor_id,
+            env::storage_usage() - initial_storage_usage,
+        );
+        log!("Created new campaign #{} of {}", index, amount);
+        index
+    }
+}
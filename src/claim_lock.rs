@@ -0,0 +1,43 @@
+use crate::*;
+
+/// The time after which an unreleased claim lock is considered stale, e.g. if the callback has
+/// run out of gas.
+pub const CLAIM_LOCK_TIMEOUT_SEC: TimestampSec = 10 * 60;
+
+#[near_bindgen]
+impl Contract {
+    /// Returns the start time of the account's in-flight claim, if any.
+    pub fn get_claim_lock(&self, account_id: ValidAccountId) -> Option<TimestampSec> {
+        self.claim_locks.get(account_id.as_ref())
+    }
+}
+
+impl Contract {
+    /// Locks the account's claims until `after_ft_transfer` resolves the transfers, so the
+    /// claims of one account never interleave. A stale lock is taken over after the timeout.
+    pub(crate) fn internal_lock_claims(&mut self, account_id: &AccountId) {
+        assert!(
+            !self.internal_is_claim_locked(account_id),
+            "The previous claim is still in progress"
+        );
+        if self
+            .claim_locks
+            .insert(account_id, &current_timestamp_sec())
+            .is_some()
+        {
+            log!("Taking over the stale claim lock of {}", account_id);
+        }
+    }
+
+    /// Returns true if the account has a claim in flight, that hasn't timed out.
+    pub(crate) fn internal_is_claim_locked(&self, account_id: &AccountId) -> bool {
+        self.claim_locks
+            .get(account_id)
+            .map(|locked_at| current_timestamp_sec() < locked_at + CLAIM_LOCK_TIMEOUT_SEC)
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn internal_unlock_claims(&mut self, account_id: &AccountId) {
+        self.claim_locks.remove(account_id);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::*;
+
+/// The period before the deadline, when the lockup views warn that the lockup expires soon.
+pub const CLAIM_DEADLINE_WARNING_PERIOD: TimestampSec = 30 * 24 * 60 * 60;
+
+/// The shortest contract-wide claim period, so the deadline is always preceded by the warning.
+pub const MIN_CLAIM_PERIOD: TimestampSec = CLAIM_DEADLINE_WARNING_PERIOD;
+
+/// The contract-wide claim deadline, that applies to the lockups without their own deadline.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Deserialize))]
+pub struct ClaimPeriod {
+    /// The time the beneficiary has to claim after the lockup is fully unlocked.
+    pub duration: TimestampSec,
+    /// The timestamp the period applies from. The period of older lockups starts from it.
+    pub since: TimestampSec,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the account that can claw back the unclaimed balances after the claim deadline.
+    #[payable]
+    pub fn set_treasury(&mut self, treasury_id: Option<ValidAccountId>) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.treasury_id = treasury_id.map(|a| a.into());
+        event::emit(Event::TreasuryUpdated {
+            treasury_id: self.treasury_id.clone(),
+        });
+    }
+
+    /// Sets the contract-wide time to claim after a lockup is fully unlocked.
+    #[payable]
+    pub fn set_claim_period(&mut self, duration: Option<TimestampSec>) {
+        assert_one_yocto();
+        self.assert_owner();
+        let now = current_timestamp_sec();
+        let current_period = self.claim_period.take();
+        self.claim_period = duration.map(|duration| {
+            assert!(
+                duration >= MIN_CLAIM_PERIOD,
+                "The claim period is too short"
+            );
+            // A shorter period starts later, so the lockups that have already finished keep at
+            // least the time to claim they had.
+            let since = match current_period {
+                Some(period) => std::cmp::max(now, period.since)
+                    .saturating_add(period.duration.saturating_sub(duration)),
+                None => now,
+            };
+            ClaimPeriod { duration, since }
+        });
+        event::emit(Event::ClaimPeriodUpdated {
+            claim_period: self.claim_period.clone(),
+        });
+    }
+
+    pub fn get_treasury(&self) -> Option<AccountId> {
+        self.treasury_id.clone()
+    }
+
+    pub fn get_claim_period(&self) -> Option<ClaimPeriod> {
+        self.claim_period.clone()
+    }
+
+    /// Transfers the unclaimed balance of the lockup to the treasury, once the claim deadline
+    /// has passed. Can only be called by the treasury.
+    pub fn claw_back(&mut self, lockup_index: LockupIndex) -> PromiseOrValue<WrappedBalance> {
+        self.assert_not_paused(PauseKind::Terminate);
+        let treasury_id = env::predecessor_account_id();
+        assert_eq!(
+            self.treasury_id.as_ref(),
+            Some(&treasury_id),
+            "Unauthorized"
+        );
+        let account_id: AccountId = self.internal_unwrap_lockup(lockup_index).account_id.into();
+        self.internal_lift_expired_blacklist(&account_id);
+        let mut lockup = self.internal_unwrap_lockup(lockup_index);
+        // The claim deadline is delayed by the beneficiary's active freeze.
+        assert!(
+            self.internal_is_claim_deadline_passed(
+                &self.internal_unwrap_frozen_lockup(lockup_index)
+            ),
+            "The claim deadline hasn't passed"
+        );
+        assert_eq!(
+            self.internal_staked_balance(lockup_index),
+            0,
+            "The lockup balance is staked, unstake it first"
+        );
+        let token_account_id = lockup.token_account_id.clone();
+        let amount = lockup.schedule.total_balance() - lockup.claimed_balance;
+        lockup.schedule.terminate(lockup.claimed_balance);
+        lockup.schedule_id = None;
+        lockup.termination_config = None;
+        self.internal_save_lockup(lockup_index, lockup);
+        self.internal_remove_finished_lockups(&account_id, vec![lockup_index]);
+        event::emit(Event::LockupClawedBack {
+            lockup_index,
+            account_id: account_id.clone(),
+            treasury_id: treasury_id.clone(),
+            amount: amount.into(),
+        });
+        if amount > 0 {
+            self.internal_update_balance_history(&account_id, &token_account_id, |snapshot| {
+                snapshot.locked_balance -= amount
+            });
+            self.internal_transfer(
+                &token_account_id,
+                treasury_id.clone(),
+                amount,
+                format!("Clawed back lockup #{}", lockup_index),
+            )
+            .then(ext_self::after_lockup_termination(
+                treasury_id,
+                token_account_id,
+                amount.into(),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_AFTER_FT_TRANSFER,
+            ))
+            .into()
+        } else {
+            PromiseOrValue::Value(0.into())
+        }
+    }
+}
+
+impl Contract {
+    /// Returns the lockup's own claim deadline, or the one from the contract-wide claim period.
+    pub(crate) fn internal_claim_deadline(&self, lockup: &Lockup) -> Option<TimestampSec> {
+        lockup.claim_deadline.or_else(|| {
+            self.claim_period.as_ref().map(|claim_period| {
+                let finished_at = lockup.schedule.0.last().unwrap().timestamp;
+                std::cmp::max(
+                    std::cmp::max(finished_at, lockup.created_at.unwrap_or(0)),
+                    claim_period.since,
+                )
+                .saturating_add(claim_period.duration)
+            })
+        })
+    }
+
+    pub(crate) fn internal_is_claim_deadline_passed(&self, lockup: &Lockup) -> bool {
+        self.internal_claim_deadline(lockup)
+            .map(|claim_deadline| current_timestamp_sec() >= claim_deadline)
+            .unwrap_or(false)
+    }
+}
@@ -0,0 +1,69 @@
+use crate::*;
+
+pub const EVENT_STANDARD: &str = "ft-lockup";
+pub const EVENT_VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    OwnerProposed {
+        owner_id: AccountId,
+        pending_owner_id: AccountId,
+    },
+    OwnerProposalCancelled {
+        owner_id: AccountId,
+        pending_owner_id: AccountId,
+    },
+    OwnershipTransferred {
+        old_owner_id: AccountId,
+        new_owner_id: AccountId,
+    },
+    Blacklisted {
+        account_id: AccountId,
+        reason: String,
+        expires_at: Option<TimestampSec>,
+        freeze: bool,
+    },
+    BlacklistLifted {
+        account_id: AccountId,
+        frozen_duration: TimestampSec,
+    },
+    Paused {
+        kind: PauseKind,
+    },
+    Unpaused {
+        kind: PauseKind,
+    },
+    TreasuryUpdated {
+        treasury_id: Option<AccountId>,
+    },
+    ClaimPeriodUpdated {
+        claim_period: Option<ClaimPeriod>,
+    },
+    LockupClawedBack {
+        lockup_index: LockupIndex,
+        account_id: AccountId,
+        treasury_id: AccountId,
+        amount: WrappedBalance,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Logs the event in the NEP-297 format, so indexers can pick it up.
+pub(crate) fn emit(event: Event) {
+    let log = EventLog {
+        standard: EVENT_STANDARD,
+        version: EVENT_VERSION,
+        event: &event,
+    };
+    env::log(format!("EVENT_JSON:{}", serde_json::to_string(&log).unwrap()).as_bytes());
+}
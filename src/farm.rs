@@ -0,0 +1,247 @@
+use crate::*;
+
+const GAS_FOR_FT_TRANSFER_CALL: Gas = 60_000_000_000_000;
+const GAS_FOR_FARM_UNSTAKE: Gas = 50_000_000_000_000;
+/// Enough to transfer the unstaked balance, that is owed to the terminator.
+const GAS_FOR_AFTER_FARM_CALL: Gas =
+    20_000_000_000_000 + GAS_FOR_FT_TRANSFER + GAS_FOR_AFTER_FT_TRANSFER + GAS_FOR_CALL_SCHEDULING;
+
+/// The interface of the staking contracts, that hold the staked balance for the lockup
+/// contract. The balance is staked with `ft_transfer_call` with `FarmStakeMessage` as msg.
+#[ext_contract(ext_farm)]
+pub trait Farm {
+    /// Transfers the given staked principal of the account back to the lockup contract, and
+    /// the account's rewards to the account.
+    fn unstake(&mut self, account_id: AccountId, amount: WrappedBalance);
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Deserialize))]
+pub struct FarmStakeMessage {
+    /// The beneficiary of the lockup, who receives the rewards.
+    pub account_id: AccountId,
+}
+
+/// The principal of a lockup that is staked in a farm.
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Deserialize))]
+pub struct LockupStake {
+    pub farm_id: AccountId,
+    #[serde(with = "u128_dec_format")]
+    pub balance: Balance,
+    /// The part of the staked balance, that was unvested when the lockup was terminated.
+    /// It's transferred to the terminator once it's unstaked.
+    #[serde(with = "u128_dec_format")]
+    pub terminated_balance: Balance,
+    pub terminator_id: Option<AccountId>,
+}
+
+#[near_bindgen]
+impl Contract {
+    #[payable]
+    pub fn add_to_farm_whitelist(&mut self, farm_id: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.farm_whitelist.insert(farm_id.as_ref());
+    }
+
+    /// Stops new stakes into the farm. The staked balances can still be unstaked.
+    #[payable]
+    pub fn remove_from_farm_whitelist(&mut self, farm_id: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.farm_whitelist.remove(farm_id.as_ref());
+    }
+
+    pub fn get_farm_whitelist(
+        &self,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<AccountId> {
+        paginate_vector(self.farm_whitelist.as_vector(), from_index, limit)
+    }
+
+    pub fn get_lockup_stake(&self, lockup_index: LockupIndex) -> Option<LockupStake> {
+        self.lockup_stakes.get(&lockup_index)
+    }
+
+    /// Stakes the given amount (or all) of the still locked balance of the lockup into the farm.
+    /// The staked balance can't be claimed until it's unstaked.
+    /// Can only be called by the beneficiary.
+    #[payable]
+    pub fn stake(
+        &mut self,
+        lockup_index: LockupIndex,
+        farm_id: ValidAccountId,
+        amount: Option<WrappedBalance>,
+    ) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.assert_not_blacklisted(&account_id);
+        let farm_id: AccountId = farm_id.into();
+        assert!(
+            self.farm_whitelist.contains(&farm_id),
+            "Not in farm whitelist"
+        );
+        let lockup = self.internal_unwrap_lockup(lockup_index);
+        assert_eq!(lockup.account_id.as_ref(), &account_id, "Unauthorized");
+        assert_ne!(
+            lockup.token_account_id, NEAR_TOKEN_ID,
+            "Native NEAR lockups can't be staked"
+        );
+        let mut stake = self
+            .lockup_stakes
+            .get(&lockup_index)
+            .unwrap_or_else(|| LockupStake {
+                farm_id: farm_id.clone(),
+                balance: 0,
+                terminated_balance: 0,
+                terminator_id: None,
+            });
+        assert_eq!(
+            stake.farm_id, farm_id,
+            "The lockup is staked in another farm"
+        );
+        let locked_balance = lockup.schedule.total_balance()
+            - lockup.schedule.unlocked_balance(current_timestamp_sec());
+        let available_balance =
+            locked_balance.saturating_sub(stake.balance - stake.terminated_balance);
+        let amount = amount.map(|a| a.0).unwrap_or(available_balance);
+        assert!(amount > 0, "Nothing to stake");
+        assert!(
+            amount <= available_balance,
+            "Only the locked balance can be staked"
+        );
+        stake.balance += amount;
+        self.lockup_stakes.insert(&lockup_index, &stake);
+        ext_fungible_token::ft_transfer_call(
+            farm_id,
+            amount.into(),
+            Some(format!("Staking lockup #{}", lockup_index)),
+            serde_json::to_string(&FarmStakeMessage { account_id }).unwrap(),
+            &lockup.token_account_id,
+            ONE_YOCTO,
+            GAS_FOR_FT_TRANSFER_CALL,
+        )
+        .then(ext_self::after_stake(
+            lockup_index,
+            amount.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_AFTER_FARM_CALL,
+        ))
+    }
+
+    /// Unstakes the given amount (or all) of the lockup's staked balance back to the lockup.
+    /// The unstaked balance pays the terminated balance first.
+    /// Can be called by the beneficiary, the terminator, or the treasury after the claim
+    /// deadline.
+    #[payable]
+    pub fn unstake(
+        &mut self,
+        lockup_index: LockupIndex,
+        amount: Option<WrappedBalance>,
+    ) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let lockup = self.internal_unwrap_lockup(lockup_index);
+        let stake = self
+            .lockup_stakes
+            .get(&lockup_index)
+            .expect("The lockup is not staked");
+        let is_terminator = lockup
+            .termination_config
+            .as_ref()
+            .map(|config| config.terminator_id.as_ref() == &account_id)
+            .unwrap_or(false)
+            || stake.terminator_id.as_ref() == Some(&account_id);
+        let is_treasury = self.treasury_id.as_ref() == Some(&account_id)
+            && self.internal_is_claim_deadline_passed(
+                &self.internal_unwrap_frozen_lockup(lockup_index),
+            );
+        assert!(
+            lockup.account_id.as_ref() == &account_id || is_terminator || is_treasury,
+            "Unauthorized"
+        );
+        let amount = amount.map(|a| a.0).unwrap_or(stake.balance);
+        assert!(
+            amount > 0 && amount <= stake.balance,
+            "Invalid unstake amount"
+        );
+        ext_farm::unstake(
+            lockup.account_id.into(),
+            amount.into(),
+            &stake.farm_id,
+            NO_DEPOSIT,
+            GAS_FOR_FARM_UNSTAKE,
+        )
+        .then(ext_self::after_unstake(
+            lockup_index,
+            amount.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_AFTER_FARM_CALL,
+        ))
+    }
+}
+
+impl Contract {
+    /// Returns the staked balance of the lockup, without the terminated balance.
+    pub(crate) fn internal_staked_balance(&self, lockup_index: LockupIndex) -> Balance {
+        self.lockup_stakes
+            .get(&lockup_index)
+            .map(|stake| stake.balance - stake.terminated_balance)
+            .unwrap_or(0)
+    }
+
+    /// Moves the given amount of the lockup's staked balance to the terminated balance, that
+    /// is owed to the terminator.
+    pub(crate) fn internal_terminate_stake(
+        &mut self,
+        lockup_index: LockupIndex,
+        terminator_id: &AccountId,
+        amount: Balance,
+    ) {
+        let mut stake = self.lockup_stakes.get(&lockup_index).unwrap();
+        stake.terminated_balance += amount;
+        stake.terminator_id = Some(terminator_id.clone());
+        self.lockup_stakes.insert(&lockup_index, &stake);
+    }
+
+    /// Reduces the lockup's staked balance, once the balance is back in the contract.
+    /// The terminated balance is released first and transferred to the terminator.
+    pub(crate) fn internal_release_stake(&mut self, lockup_index: LockupIndex, amount: Balance) {
+        let mut stake = match self.lockup_stakes.get(&lockup_index) {
+            Some(stake) => stake,
+            None => return,
+        };
+        let terminated_amount = std::cmp::min(amount, stake.terminated_balance);
+        stake.terminated_balance -= terminated_amount;
+        stake.balance = stake.balance.saturating_sub(amount);
+        if stake.balance == 0 {
+            self.lockup_stakes.remove(&lockup_index);
+        } else {
+            self.lockup_stakes.insert(&lockup_index, &stake);
+        }
+        if terminated_amount > 0 {
+            let terminator_id = stake.terminator_id.unwrap();
+            let token_account_id = self.internal_unwrap_lockup(lockup_index).token_account_id;
+            self.internal_transfer(
+                &token_account_id,
+                terminator_id.clone(),
+                terminated_amount,
+                format!("Terminated lockup #{}", lockup_index),
+            )
+            .then(ext_self::after_lockup_termination(
+                terminator_id,
+                token_account_id,
+                terminated_amount.into(),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_AFTER_FT_TRANSFER,
+            ));
+        }
+    }
+}
@@ -8,21 +8,47 @@ impl FungibleTokenReceiver for Contract {
         amount: U128,
         msg: String,
     ) -> PromiseOrValue<U128> {
-        assert_eq!(
-            env::predecessor_account_id(),
-            self.token_account_id,
+        let token_account_id = env::predecessor_account_id();
+        assert!(
+            self.token_whitelist.contains(&token_account_id),
             "Invalid token ID"
         );
         self.assert_deposit_whitelist(sender_id.as_ref());
-        let lockup: Lockup = serde_json::from_str(&msg).expect("Expected Lockup as msg");
-        let amount = amount.into();
-        lockup.assert_new_valid(amount);
-        let index = self.internal_add_lockup(&lockup);
-        log!(
-            "Created new lockup for {} with index {}",
-            lockup.account_id.as_ref(),
-            index
-        );
+        if self.paused.deposit {
+            log!("Deposits are paused. Refunding {}", amount.0);
+            return PromiseOrValue::Value(amount);
+        }
+        let message: FtOnTransferMessage =
+            serde_json::from_str(&msg).expect("Expected Lockup, campaign or voucher pool as msg");
+        match message {
+            FtOnTransferMessage::Campaign { campaign } => {
+                self.internal_create_campaign(
+                    sender_id.as_ref(),
+                    token_account_id,
+                    campaign,
+                    amount.into(),
+                );
+            }
+            FtOnTransferMessage::VoucherPool { .. } => {
+                self.internal_fund_voucher_pool(
+                    sender_id.as_ref(),
+                    token_account_id,
+                    amount.into(),
+                );
+            }
+            FtOnTransferMessage::Lockup(deposit) => {
+                let DepositMessage {
+                    mut lockup,
+                    notify,
+                    notify_msg,
+                } = *deposit;
+                lockup.token_account_id = token_account_id;
+                let index = self.internal_create_lockup(sender_id.as_ref(), lockup, amount.into());
+                if notify {
+                    self.internal_notify_lockup_created(index, notify_msg);
+                }
+            }
+        }
         PromiseOrValue::Value(0.into())
     }
 }
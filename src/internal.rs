@@ -1,4 +1,19 @@
 use crate::*;
+use std::convert::TryInto;
+
+/// The gas `internal_claim` keeps to schedule the callback and to finish.
+const GAS_RESERVED_FOR_CLAIM: Gas = 10_000_000_000_000;
+/// The gas `internal_claim` needs to schedule the transfer of every token, and to pass its
+/// result to the callback.
+const GAS_FOR_TOKEN_CLAIM: Gas = GAS_FOR_CALL_SCHEDULING;
+
+/// Returns the gas `internal_claim` needs to claim the given number of lockups and tokens,
+/// including the transfers and the callback.
+pub(crate) const fn gas_for_claim(num_tokens: usize, num_lockup_claims: usize) -> Gas {
+    GAS_RESERVED_FOR_CLAIM
+        + (GAS_FOR_FT_TRANSFER + GAS_FOR_TOKEN_CLAIM) * num_tokens as Gas
+        + gas_for_after_ft_transfer(num_tokens, num_lockup_claims)
+}
 
 impl Contract {
     pub(crate) fn assert_deposit_whitelist(&self, account_id: &AccountId) {
@@ -8,23 +23,273 @@ impl Contract {
         );
     }
 
-    pub(crate) fn internal_add_lockup(&mut self, lockup: &Lockup) -> LockupIndex {
+    /// Validates the new lockup with the deposited amount, adds it and charges its storage to
+    /// the depositor.
+    pub(crate) fn internal_create_lockup(
+        &mut self,
+        depositor_id: &AccountId,
+        mut lockup: Lockup,
+        amount: Balance,
+    ) -> LockupIndex {
+        if let Some(schedule_id) = lockup.schedule_id {
+            assert!(
+                lockup.schedule.0.is_empty(),
+                "The schedule should be omitted if schedule_id is given"
+            );
+            lockup.schedule = self.internal_resolve_schedule(&schedule_id.into(), amount);
+        }
+        lockup.assert_new_valid(amount);
+        lockup.creator_id = Some(depositor_id.clone());
+        lockup.created_at = Some(current_timestamp_sec());
+        let account_id: AccountId = lockup.account_id.clone().into();
+        // The legacy account is migrated first, so the depositor doesn't pay for it.
+        self.internal_migrate_account_lockups(&account_id);
+        let initial_storage_usage = env::storage_usage();
+        let index = self.internal_add_lockup(lockup);
+        self.internal_charge_lockup_storage(depositor_id, index, initial_storage_usage);
+        log!("Created new lockup for {} with index {}", account_id, index);
+        index
+    }
+
+    /// Claims the unlocked balance of the account's lockups and the withdrawable balances,
+    /// optionally only of the given token. The keeper fee is deducted from the transfers.
+    /// The lockups, that don't fit into the prepaid gas, are left for the next claim.
+    pub(crate) fn internal_claim(
+        &mut self,
+        account_id: AccountId,
+        token_account_id: Option<TokenAccountId>,
+        keeper_fee: Option<KeeperFee>,
+    ) -> PromiseOrValue<WrappedBalance> {
+        let mut token_claims: Vec<(TokenClaims, Balance)> = vec![];
+        // Retrying the transfers of the withdrawable balances.
+        for (token_account_id, pending_balance) in
+            self.internal_take_withdrawable(&account_id, token_account_id.as_ref())
+        {
+            log!(
+                "Claiming withdrawable {} {}",
+                pending_balance,
+                token_account_id
+            );
+            token_claims.push((
+                TokenClaims {
+                    token_account_id,
+                    lockup_claims: vec![],
+                    pending_balance,
+                    keeper_fee: 0,
+                },
+                0,
+            ));
+        }
+
+        let mut required_gas = gas_for_claim(token_claims.len(), 0);
+        let mut num_lockup_claims = 0;
+        let lockups = self.internal_get_account_lockups(&account_id);
+        for (lockup_index, mut lockup) in lockups {
+            if let Some(token_account_id) = &token_account_id {
+                if token_account_id != &lockup.token_account_id {
+                    continue;
+                }
+            }
+            let staked_balance = self.internal_staked_balance(lockup_index);
+            let lockup_claim = lockup.claim(lockup_index, staked_balance);
+            if lockup_claim.unclaimed_balance.0 > 0 {
+                let position = token_claims
+                    .iter()
+                    .position(|(claims, _)| claims.token_account_id == lockup.token_account_id);
+                let mut claim_gas = GAS_FOR_AFTER_CLAIM_TRANSFER_PER_LOCKUP;
+                if position.is_none() {
+                    claim_gas += GAS_FOR_FT_TRANSFER
+                        + GAS_FOR_TOKEN_CLAIM
+                        + GAS_FOR_AFTER_CLAIM_TRANSFER_PER_TOKEN;
+                }
+                if env::used_gas() + required_gas + claim_gas > env::prepaid_gas() {
+                    log!("Not enough gas to claim the rest of the lockups");
+                    break;
+                }
+                required_gas += claim_gas;
+                num_lockup_claims += 1;
+                log!(
+                    "Claiming {} {} form lockup #{}",
+                    lockup_claim.unclaimed_balance.0,
+                    lockup.token_account_id,
+                    lockup_index
+                );
+                let (claims, unclaimed_balance) = match position {
+                    Some(position) => &mut token_claims[position],
+                    None => {
+                        token_claims.push((
+                            TokenClaims {
+                                token_account_id: lockup.token_account_id.clone(),
+                                lockup_claims: vec![],
+                                pending_balance: 0,
+                                keeper_fee: 0,
+                            },
+                            0,
+                        ));
+                        token_claims.last_mut().unwrap()
+                    }
+                };
+                *unclaimed_balance += lockup_claim.unclaimed_balance.0;
+                claims.lockup_claims.push(lockup_claim);
+                self.internal_save_lockup(lockup_index, lockup);
+            }
+        }
+
+        let mut transfers: Option<Promise> = None;
+        for (claims, unclaimed_balance) in &mut token_claims {
+            let unclaimed_balance = *unclaimed_balance;
+            if unclaimed_balance > 0 {
+                self.internal_update_balance_history(
+                    &account_id,
+                    &claims.token_account_id,
+                    |snapshot| {
+                        snapshot.locked_balance -= unclaimed_balance;
+                        snapshot.claimed_balance += unclaimed_balance;
+                    },
+                );
+            }
+            let mut amount = unclaimed_balance + claims.pending_balance;
+            if let Some(keeper_fee) = &keeper_fee {
+                claims.keeper_fee = keeper_fee.fee(amount);
+                amount -= claims.keeper_fee;
+                log!(
+                    "Keeper fee {} {}",
+                    claims.keeper_fee,
+                    claims.token_account_id
+                );
+            }
+            log!("Total claim {} {}", amount, claims.token_account_id);
+            let transfer = self.internal_transfer(
+                &claims.token_account_id,
+                account_id.clone(),
+                amount,
+                format!(
+                    "Claiming unlocked {} balance from {}",
+                    amount,
+                    env::current_account_id()
+                ),
+            );
+            transfers = Some(match transfers {
+                Some(transfers) => transfers.and(transfer),
+                None => transfer,
+            });
+        }
+
+        match transfers {
+            Some(transfers) => {
+                self.internal_lock_claims(&account_id);
+                let callback_gas = gas_for_after_ft_transfer(token_claims.len(), num_lockup_claims);
+                transfers
+                    .then(ext_self::after_ft_transfer(
+                        account_id,
+                        token_claims.into_iter().map(|(claims, _)| claims).collect(),
+                        keeper_fee.map(|keeper_fee| keeper_fee.keeper_id),
+                        &env::current_account_id(),
+                        NO_DEPOSIT,
+                        callback_gas,
+                    ))
+                    .into()
+            }
+            None => PromiseOrValue::Value(0.into()),
+        }
+    }
+
+    /// Transfers the token, or native NEAR for `NEAR_TOKEN_ID`.
+    /// The transferred NEAR is no longer held for the lockups, unless the transfer fails.
+    pub(crate) fn internal_transfer(
+        &mut self,
+        token_account_id: &TokenAccountId,
+        receiver_id: AccountId,
+        amount: Balance,
+        memo: String,
+    ) -> Promise {
+        if token_account_id == NEAR_TOKEN_ID {
+            self.near_locked_balance -= amount;
+            let transfer = Promise::new(receiver_id).transfer(amount);
+            self.assert_near_locked_balance();
+            transfer
+        } else {
+            ext_fungible_token::ft_transfer(
+                receiver_id,
+                amount.into(),
+                Some(memo),
+                token_account_id,
+                ONE_YOCTO,
+                GAS_FOR_FT_TRANSFER,
+            )
+        }
+    }
+
+    pub(crate) fn internal_add_lockup(&mut self, mut lockup: Lockup) -> LockupIndex {
         let index = self.lockups.len() as LockupIndex;
-        self.lockups.push(lockup);
-        let mut indices = self
-            .account_lockups
-            .get(lockup.account_id.as_ref())
-            .unwrap_or_default();
-        indices.insert(index);
-        self.internal_save_account_lockups(lockup.account_id.as_ref(), indices);
+        let account_id: AccountId = lockup.account_id.clone().into();
+        // The freezes lifted before the lockup is created don't delay it.
+        lockup.num_applied_freezes = self.internal_get_lifted_freezes(&account_id).len() as u32;
+        let token_account_id = lockup.token_account_id.clone();
+        let total_balance = lockup.schedule.total_balance() - lockup.claimed_balance;
+        self.lockups.push(&lockup.into());
+        self.internal_update_balance_history(&account_id, &token_account_id, |snapshot| {
+            snapshot.locked_balance += total_balance
+        });
+        self.internal_insert_account_lockups(&account_id, &[index]);
         index
     }
 
-    pub(crate) fn internal_save_account_lockups(
+    /// Removes the finished lockups from the account's lockups, releases their storage and
+    /// archives them, if the compaction is enabled.
+    pub(crate) fn internal_remove_finished_lockups(
         &mut self,
         account_id: &AccountId,
-        indices: HashSet<LockupIndex>,
+        indices: Vec<LockupIndex>,
     ) {
+        if indices.is_empty() {
+            return;
+        }
+        let initial_storage_usage = env::storage_usage();
+        self.internal_remove_account_lockups(account_id, &indices);
+        // The freed storage is split between the removed lockups.
+        let freed_bytes = initial_storage_usage.saturating_sub(env::storage_usage());
+        let num_removed = indices.len() as StorageUsage;
+        for (i, index) in indices.into_iter().enumerate() {
+            let mut lockup_freed_bytes = freed_bytes / num_removed;
+            if i == 0 {
+                lockup_freed_bytes += freed_bytes % num_removed;
+            }
+            self.internal_release_lockup_storage(index, lockup_freed_bytes);
+            if self.compaction_enabled {
+                self.internal_archive_lockup(index);
+            }
+        }
+    }
+
+    /// Adds the missing indices to the account's lockups. Returns true if any was added.
+    pub(crate) fn internal_insert_account_lockups(
+        &mut self,
+        account_id: &AccountId,
+        lockup_indices: &[LockupIndex],
+    ) -> bool {
+        self.internal_migrate_account_lockups(account_id);
+        let mut indices = self.internal_get_account_lockup_set(account_id);
+        let mut modified = false;
+        for lockup_index in lockup_indices {
+            modified |= indices.insert(lockup_index);
+        }
+        if modified {
+            self.account_lockups.insert(account_id, &indices);
+        }
+        modified
+    }
+
+    pub(crate) fn internal_remove_account_lockups(
+        &mut self,
+        account_id: &AccountId,
+        lockup_indices: &[LockupIndex],
+    ) {
+        self.internal_migrate_account_lockups(account_id);
+        let mut indices = self.internal_get_account_lockup_set(account_id);
+        for lockup_index in lockup_indices {
+            indices.remove(lockup_index);
+        }
         if indices.is_empty() {
             self.account_lockups.remove(account_id);
         } else {
@@ -32,15 +297,201 @@ impl Contract {
         }
     }
 
+    /// Returns the set of the account's lockup indices. Every account has its own storage
+    /// prefix, so a change only writes the changed indices.
+    fn internal_get_account_lockup_set(&self, account_id: &AccountId) -> UnorderedSet<LockupIndex> {
+        self.account_lockups.get(account_id).unwrap_or_else(|| {
+            UnorderedSet::new(StorageKey::AccountLockupSet {
+                account_hash: env::sha256(account_id.as_bytes()).try_into().unwrap(),
+            })
+        })
+    }
+
+    /// Returns the account's lockup indices, falling back to the legacy layout for the
+    /// accounts that are not migrated yet.
+    pub(crate) fn internal_get_account_lockup_indices(
+        &self,
+        account_id: &AccountId,
+    ) -> Vec<LockupIndex> {
+        if let Some(indices) = self.account_lockups.get(account_id) {
+            return indices.to_vec();
+        }
+        self.internal_get_legacy_account_lockup_indices(account_id)
+    }
+
+    /// Returns a page of the account's lockup indices.
+    pub(crate) fn internal_get_account_lockup_indices_page(
+        &self,
+        account_id: &AccountId,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<LockupIndex> {
+        if let Some(indices) = self.account_lockups.get(account_id) {
+            return paginate_vector(indices.as_vector(), from_index, limit);
+        }
+        let indices = self.internal_get_legacy_account_lockup_indices(account_id);
+        paginate(&indices, from_index, limit).to_vec()
+    }
+
+    pub(crate) fn internal_get_num_account_lockups(&self, account_id: &AccountId) -> u32 {
+        if let Some(indices) = self.account_lockups.get(account_id) {
+            return indices.len() as _;
+        }
+        self.internal_get_legacy_account_lockup_indices(account_id)
+            .len() as _
+    }
+
+    pub(crate) fn internal_has_account_lockup(
+        &self,
+        account_id: &AccountId,
+        lockup_index: LockupIndex,
+    ) -> bool {
+        if let Some(indices) = self.account_lockups.get(account_id) {
+            return indices.contains(&lockup_index);
+        }
+        self.internal_get_legacy_account_lockup_indices(account_id)
+            .contains(&lockup_index)
+    }
+
+    /// Returns the sorted lockup indices of the account in the legacy layout.
+    fn internal_get_legacy_account_lockup_indices(
+        &self,
+        account_id: &AccountId,
+    ) -> Vec<LockupIndex> {
+        let mut indices: Vec<LockupIndex> = self
+            .legacy_account_lockups
+            .as_ref()
+            .and_then(|legacy_account_lockups| legacy_account_lockups.get(account_id))
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        indices.sort_unstable();
+        indices
+    }
+
+    /// Moves the account's lockup indices from the legacy layout and seeds its balance
+    /// history. Returns false if there was nothing to migrate.
+    pub(crate) fn internal_migrate_account_lockups(&mut self, account_id: &AccountId) -> bool {
+        if self.account_lockups.contains_key(account_id) {
+            return false;
+        }
+        let legacy_indices = match self
+            .legacy_account_lockups
+            .as_mut()
+            .and_then(|legacy_account_lockups| legacy_account_lockups.remove(account_id))
+        {
+            Some(legacy_indices) => legacy_indices,
+            None => return false,
+        };
+        let mut sorted_indices: Vec<LockupIndex> = legacy_indices.iter().cloned().collect();
+        sorted_indices.sort_unstable();
+        self.internal_seed_balance_history(account_id, legacy_indices);
+        if !sorted_indices.is_empty() {
+            let mut indices = self.internal_get_account_lockup_set(account_id);
+            indices.extend(sorted_indices);
+            self.account_lockups.insert(account_id, &indices);
+        }
+        true
+    }
+
     pub(crate) fn internal_get_account_lockups(
         &self,
         account_id: &AccountId,
     ) -> Vec<(LockupIndex, Lockup)> {
-        self.account_lockups
-            .get(account_id)
-            .unwrap_or_default()
+        let freezes = self.internal_get_lifted_freezes(account_id);
+        self.internal_get_account_lockup_indices(account_id)
             .into_iter()
-            .map(|lockup_index| (lockup_index, self.lockups.get(lockup_index as _).unwrap()))
+            .map(|lockup_index| {
+                let mut lockup = self.internal_load_lockup(lockup_index);
+                lockup.apply_lifted_freezes(&freezes);
+                (lockup_index, lockup)
+            })
             .collect()
     }
+
+    /// Returns the stored lockup by index. The lockups that are not migrated yet are read from
+    /// the legacy layout.
+    pub(crate) fn internal_get_stored_lockup(
+        &self,
+        lockup_index: LockupIndex,
+    ) -> Option<StoredLockup> {
+        self.legacy_lockups
+            .as_ref()
+            .and_then(|legacy_lockups| legacy_lockups.get(lockup_index))
+            .map(StoredLockup::Lockup)
+            .or_else(|| self.lockups.get(lockup_index as _))
+    }
+
+    /// Replaces the stored lockup by index. A legacy lockup is migrated by the first change.
+    pub(crate) fn internal_replace_stored_lockup(
+        &mut self,
+        lockup_index: LockupIndex,
+        stored_lockup: &StoredLockup,
+    ) {
+        if let Some(lockup) = self
+            .legacy_lockups
+            .as_ref()
+            .and_then(|legacy_lockups| legacy_lockups.get(lockup_index))
+        {
+            self.internal_migrate_legacy_lockup(lockup_index, lockup);
+        }
+        // The replaced value isn't read, since it can be in the legacy layout.
+        self.lockups
+            .replace_raw(lockup_index as _, &stored_lockup.try_to_vec().unwrap());
+    }
+
+    /// Returns the lockup by index, delayed by the account's lifted freezes. Panics if it's
+    /// missing or archived.
+    pub(crate) fn internal_unwrap_lockup(&self, lockup_index: LockupIndex) -> Lockup {
+        let mut lockup = self.internal_load_lockup(lockup_index);
+        let freezes = self.internal_get_lifted_freezes(&lockup.account_id.clone().into());
+        lockup.apply_lifted_freezes(&freezes);
+        lockup
+    }
+
+    /// Returns the lockup by index, as it's stored.
+    fn internal_load_lockup(&self, lockup_index: LockupIndex) -> Lockup {
+        match self.internal_get_stored_lockup(lockup_index) {
+            Some(StoredLockup::Lockup(lockup)) => lockup,
+            Some(StoredLockup::Registered {
+                schedule_id,
+                total_balance,
+                mut lockup,
+            }) => {
+                lockup.schedule = self.internal_resolve_schedule(&schedule_id, total_balance);
+                lockup.schedule_id = Some(schedule_id.into());
+                lockup
+            }
+            Some(StoredLockup::Archived(_)) => env::panic(b"Lockup is archived"),
+            None => env::panic(b"Lockup not found"),
+        }
+    }
+
+    pub(crate) fn internal_save_lockup(&mut self, lockup_index: LockupIndex, lockup: Lockup) {
+        self.internal_replace_stored_lockup(lockup_index, &lockup.into());
+    }
+
+    /// Replaces the finished lockup with the archived summary and credits the freed storage
+    /// to the account that paid for it. Returns false if the lockup can't be archived.
+    pub(crate) fn internal_archive_lockup(&mut self, lockup_index: LockupIndex) -> bool {
+        let lockup = match self.internal_get_stored_lockup(lockup_index) {
+            Some(StoredLockup::Archived(_)) | None => return false,
+            Some(_) => self.internal_unwrap_lockup(lockup_index),
+        };
+        // The staked lockups are kept until the stake is released.
+        if !lockup.is_finished()
+            || self.lockup_stakes.contains_key(&lockup_index)
+            || self.internal_has_account_lockup(lockup.account_id.as_ref(), lockup_index)
+        {
+            return false;
+        }
+        let initial_storage_usage = env::storage_usage();
+        self.internal_replace_stored_lockup(
+            lockup_index,
+            &StoredLockup::Archived(lockup.archive()),
+        );
+        let freed_bytes = initial_storage_usage.saturating_sub(env::storage_usage());
+        self.internal_release_lockup_storage(lockup_index, freed_bytes);
+        true
+    }
 }
@@ -0,0 +1,230 @@
+use crate::*;
+
+/// The upper bound of the keeper fee cap, in basis points of the claimed balance.
+pub const MAX_KEEPER_FEE_BPS: u32 = 500;
+const BPS_DENOMINATOR: u32 = 10_000;
+
+/// The gas of a single account's claim in `claim_batch`, including its transfer and callback.
+const GAS_FOR_BATCH_ACCOUNT_CLAIM: Gas = gas_for_claim(1, 1);
+/// The gas of the registration check's callback in `claim_batch`, that claims for the account.
+const GAS_FOR_BATCH_ACCOUNT_CHECK: Gas =
+    GAS_FOR_AFTER_TOKEN_REGISTRATION_CHECK + GAS_FOR_BATCH_ACCOUNT_CLAIM;
+/// The gas of a single account's registration check in `claim_batch`, including the claim,
+/// but not the scan of the account's lockups.
+const GAS_FOR_BATCH_ACCOUNT_CHECK_CLAIM: Gas =
+    GAS_FOR_STORAGE_BALANCE_OF + GAS_FOR_CALL_SCHEDULING + GAS_FOR_BATCH_ACCOUNT_CHECK;
+/// The gas kept by `claim_batch` to finish its own execution.
+const GAS_RESERVED_FOR_BATCH_CLAIM: Gas = 10_000_000_000_000;
+
+/// The fee a keeper takes from the claim it pushed.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct KeeperFee {
+    pub keeper_id: AccountId,
+    pub fee_bps: u32,
+}
+
+impl KeeperFee {
+    pub fn fee(&self, amount: Balance) -> Balance {
+        (U256::from(amount) * U256::from(self.fee_bps) / U256::from(BPS_DENOMINATOR)).as_u128()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Deserialize))]
+pub struct BatchClaimResult {
+    /// The number of accounts processed from the start of the list. The remaining accounts
+    /// didn't fit into the gas, and should be submitted again.
+    pub num_processed: u32,
+    /// The processed accounts, that had something to claim.
+    pub claimed_account_ids: Vec<AccountId>,
+    /// The processed accounts, that are claimed once they are checked to be registered with
+    /// the token. The claim of an unregistered account claims nothing.
+    pub checked_account_ids: Vec<AccountId>,
+    /// The processed accounts, that are blacklisted or have a claim in flight.
+    pub skipped_account_ids: Vec<AccountId>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Claims on behalf of the account. The tokens always go to the account.
+    /// Can be called by a keeper, or by anyone if the account has allowed it.
+    /// A keeper can take a fee of up to the fee cap, in basis points of the claimed balance.
+    /// The fee is added to the keeper's withdrawable balance, once the transfer succeeds.
+    pub fn claim_for(
+        &mut self,
+        account_id: ValidAccountId,
+        token_account_id: Option<TokenAccountId>,
+        fee_bps: Option<u32>,
+    ) -> PromiseOrValue<WrappedBalance> {
+        self.assert_not_paused(PauseKind::Claim);
+        let keeper_id = env::predecessor_account_id();
+        let account_id: AccountId = account_id.into();
+        let is_keeper = self.keepers.contains(&keeper_id);
+        assert!(
+            is_keeper || self.claim_for_accounts.contains(&account_id),
+            "Not allowed to claim for {}",
+            account_id
+        );
+        self.assert_not_blacklisted(&keeper_id);
+        self.assert_not_blacklisted(&account_id);
+        if let Some(token_account_id) = &token_account_id {
+            assert_valid_token_id(token_account_id);
+        }
+        let fee_bps = fee_bps.unwrap_or(0);
+        let keeper_fee = if fee_bps > 0 {
+            assert!(is_keeper, "Only keepers can take a fee");
+            assert!(
+                fee_bps <= self.max_keeper_fee_bps,
+                "The fee is above the cap of {} bps",
+                self.max_keeper_fee_bps
+            );
+            Some(KeeperFee { keeper_id, fee_bps })
+        } else {
+            None
+        };
+        self.internal_claim_with_registration_check(account_id, token_account_id, keeper_fee)
+    }
+
+    /// Claims the given token on behalf of the accounts, without a fee. Can only be called by
+    /// a keeper. Every account gets its own transfer and callback, so a failed transfer
+    /// doesn't affect the other accounts. The accounts, that aren't known to be registered
+    /// with the token, are checked first, like in `claim`.
+    /// Stops once the remaining gas isn't enough for the next account. With 300 TGas, a batch
+    /// fits 5 accounts with a single lockup, that are known to be registered, or 3 accounts,
+    /// that are checked first.
+    pub fn claim_batch(
+        &mut self,
+        account_ids: Vec<ValidAccountId>,
+        token_account_id: TokenAccountId,
+    ) -> BatchClaimResult {
+        self.assert_not_paused(PauseKind::Claim);
+        let keeper_id = env::predecessor_account_id();
+        assert!(self.keepers.contains(&keeper_id), "Not a keeper");
+        self.assert_not_blacklisted(&keeper_id);
+        assert_valid_token_id(&token_account_id);
+        let mut result = BatchClaimResult {
+            num_processed: 0,
+            claimed_account_ids: vec![],
+            checked_account_ids: vec![],
+            skipped_account_ids: vec![],
+        };
+        for account_id in account_ids {
+            let account_id: AccountId = account_id.into();
+            let is_registered = token_account_id == NEAR_TOKEN_ID
+                || self.internal_is_token_registered(&account_id, &token_account_id);
+            let account_gas = if is_registered {
+                GAS_FOR_BATCH_ACCOUNT_CLAIM
+            } else {
+                GAS_FOR_BATCH_ACCOUNT_CHECK_CLAIM
+            };
+            if remaining_batch_gas() < account_gas {
+                break;
+            }
+            if self.internal_is_blacklisted(&account_id)
+                || self.internal_is_claim_locked(&account_id)
+            {
+                result.skipped_account_ids.push(account_id.clone());
+            } else if is_registered {
+                if let PromiseOrValue::Promise(_) =
+                    self.internal_claim(account_id.clone(), Some(token_account_id.clone()), None)
+                {
+                    result.claimed_account_ids.push(account_id.clone());
+                }
+            } else {
+                let scan_start_gas = env::used_gas();
+                let token_ids =
+                    self.internal_claimed_token_ids(&account_id, Some(&token_account_id));
+                if !token_ids.is_empty() {
+                    // The claim scans the account's lockups again.
+                    let check_gas = GAS_FOR_BATCH_ACCOUNT_CHECK + env::used_gas() - scan_start_gas;
+                    if remaining_batch_gas()
+                        < GAS_FOR_STORAGE_BALANCE_OF + GAS_FOR_CALL_SCHEDULING + check_gas
+                    {
+                        break;
+                    }
+                    let claim_request = ClaimRequest {
+                        account_id: account_id.clone(),
+                        token_account_id: Some(token_account_id.clone()),
+                        keeper_fee: None,
+                    };
+                    internal_check_token_registration_then_claim(
+                        claim_request,
+                        token_ids,
+                        check_gas,
+                    );
+                    result.checked_account_ids.push(account_id.clone());
+                }
+            }
+            result.num_processed += 1;
+        }
+        log!(
+            "Processed {} accounts, claimed for {}, checking {}",
+            result.num_processed,
+            result.claimed_account_ids.len(),
+            result.checked_account_ids.len()
+        );
+        result
+    }
+
+    /// Allows anyone to claim on behalf of the predecessor, without a fee.
+    #[payable]
+    pub fn set_claim_for_allowed(&mut self, allowed: bool) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        if allowed {
+            assert!(
+                self.internal_get_num_account_lockups(&account_id) > 0,
+                "No lockups to claim"
+            );
+            self.claim_for_accounts.insert(&account_id);
+        } else {
+            self.claim_for_accounts.remove(&account_id);
+        }
+    }
+
+    pub fn is_claim_for_allowed(&self, account_id: ValidAccountId) -> bool {
+        self.claim_for_accounts.contains(account_id.as_ref())
+    }
+
+    #[payable]
+    pub fn add_keeper(&mut self, keeper_id: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.keepers.insert(keeper_id.as_ref());
+    }
+
+    #[payable]
+    pub fn remove_keeper(&mut self, keeper_id: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.keepers.remove(keeper_id.as_ref());
+    }
+
+    pub fn get_keepers(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<AccountId> {
+        paginate_vector(self.keepers.as_vector(), from_index, limit)
+    }
+
+    /// Sets the cap of the keeper fee, in basis points of the claimed balance.
+    #[payable]
+    pub fn set_max_keeper_fee_bps(&mut self, max_keeper_fee_bps: u32) {
+        assert_one_yocto();
+        self.assert_owner();
+        assert!(
+            max_keeper_fee_bps <= MAX_KEEPER_FEE_BPS,
+            "The fee cap can't exceed {} bps",
+            MAX_KEEPER_FEE_BPS
+        );
+        self.max_keeper_fee_bps = max_keeper_fee_bps;
+    }
+
+    pub fn get_max_keeper_fee_bps(&self) -> u32 {
+        self.max_keeper_fee_bps
+    }
+}
+
+/// Returns the prepaid gas of `claim_batch`, that is left for the next accounts.
+fn remaining_batch_gas() -> Gas {
+    env::prepaid_gas().saturating_sub(env::used_gas() + GAS_RESERVED_FOR_BATCH_CLAIM)
+}
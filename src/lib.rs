@@ -1,39 +1,85 @@
 use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
 use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
 use near_contract_standards::upgrade::Ownable;
-use near_sdk::borsh::maybestd::collections::HashSet;
+use near_sdk::borsh::maybestd::collections::{HashMap, HashSet};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap, UnorderedSet, Vector};
+use near_sdk::collections::{LookupMap, LookupSet, UnorderedMap, UnorderedSet, Vector};
 use near_sdk::json_types::{Base58CryptoHash, ValidAccountId, WrappedBalance, U128};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
     assert_one_yocto, env, ext_contract, is_promise_success, log, near_bindgen, serde_json,
-    AccountId, Balance, BorshStorageKey, CryptoHash, Gas, PanicOnDefault, PromiseOrValue,
-    Timestamp,
+    AccountId, Balance, BorshStorageKey, CryptoHash, Gas, PanicOnDefault, Promise, PromiseOrValue,
+    StorageUsage, Timestamp,
 };
 
+pub mod balance_history;
+pub mod blacklist;
 pub mod callbacks;
+pub mod campaign;
+pub mod claim_lock;
+pub mod clawback;
+pub mod event;
+pub mod farm;
 pub mod ft_token_receiver;
 pub mod internal;
+pub mod keeper;
 pub mod lockup;
 pub mod migrate;
+pub mod near_lockup;
+pub mod notification;
+pub mod owner;
+pub mod pause;
 pub mod schedule;
+pub mod schedule_registry;
+pub mod storage;
 pub mod termination;
+pub mod token_registration;
 pub mod util;
 pub mod view;
+pub mod voting_power;
+pub mod voucher;
+pub mod withdraw;
 
+use crate::balance_history::*;
+use crate::blacklist::*;
+use crate::campaign::*;
+use crate::clawback::*;
+use crate::event::Event;
+use crate::farm::*;
+use crate::internal::*;
+use crate::keeper::*;
 use crate::lockup::*;
+use crate::migrate::*;
+use crate::notification::*;
+use crate::pause::*;
 use crate::schedule::*;
+use crate::storage::*;
 use crate::termination::*;
+use crate::token_registration::*;
 use crate::util::*;
+use crate::voting_power::*;
+use crate::voucher::*;
 
 near_sdk::setup_alloc!();
 
 pub type TimestampSec = u32;
 pub type TokenAccountId = AccountId;
 
+/// The token ID of the lockups in native NEAR. It's not a valid account ID, so no token
+/// contract can have it.
+pub const NEAR_TOKEN_ID: &str = "NEAR";
+
 const GAS_FOR_FT_TRANSFER: Gas = 15_000_000_000_000;
 const GAS_FOR_AFTER_FT_TRANSFER: Gas = 20_000_000_000_000;
+/// The gas of the claim's `after_ft_transfer` itself, without the tokens and the lockups.
+const GAS_FOR_AFTER_CLAIM_TRANSFER: Gas = 5_000_000_000_000;
+/// The gas of the claim's `after_ft_transfer` for every resolved token.
+const GAS_FOR_AFTER_CLAIM_TRANSFER_PER_TOKEN: Gas = 3_000_000_000_000;
+/// The gas of the claim's `after_ft_transfer` for every claimed lockup, that may be removed
+/// and archived.
+const GAS_FOR_AFTER_CLAIM_TRANSFER_PER_LOCKUP: Gas = 3_000_000_000_000;
+/// The gas burnt to schedule a call, and to pass its result to the callback.
+const GAS_FOR_CALL_SCHEDULING: Gas = 15_000_000_000_000;
 
 const ONE_YOCTO: Balance = 1;
 const NO_DEPOSIT: Balance = 0;
@@ -47,12 +93,42 @@ pub trait SelfCallbacks {
     fn after_ft_transfer(
         &mut self,
         account_id: AccountId,
-        lockup_claims: Vec<LockupClaim>,
+        token_claims: Vec<TokenClaims>,
+        keeper_id: Option<AccountId>,
     ) -> WrappedBalance;
 
+    fn after_storage_balance_check(
+        &mut self,
+        claim_request: ClaimRequest,
+        token_account_id: TokenAccountId,
+        storage_deposit: WrappedBalance,
+    ) -> PromiseOrValue<WrappedBalance>;
+
+    fn after_token_registration_check(
+        &mut self,
+        claim_request: ClaimRequest,
+        token_ids: Vec<TokenAccountId>,
+        storage_deposit: WrappedBalance,
+    ) -> PromiseOrValue<WrappedBalance>;
+
     fn after_lockup_termination(
         &mut self,
         account_id: AccountId,
+        token_account_id: TokenAccountId,
+        amount: WrappedBalance,
+    ) -> WrappedBalance;
+
+    fn after_lockup_created_notification(
+        &mut self,
+        lockup_index: LockupIndex,
+        account_id: AccountId,
+    );
+
+    fn after_stake(&mut self, lockup_index: LockupIndex, amount: WrappedBalance) -> WrappedBalance;
+
+    fn after_unstake(
+        &mut self,
+        lockup_index: LockupIndex,
         amount: WrappedBalance,
     ) -> WrappedBalance;
 }
@@ -60,26 +136,134 @@ pub trait SelfCallbacks {
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
-    pub token_account_id: TokenAccountId,
+    /// The tokens that can be locked.
+    pub token_whitelist: UnorderedSet<TokenAccountId>,
 
-    pub lockups: Vector<Lockup>,
+    pub lockups: Vector<StoredLockup>,
+    /// The lockups in the previous layout, until all of them are migrated.
+    pub legacy_lockups: Option<LegacyLockups>,
 
-    pub account_lockups: LookupMap<AccountId, HashSet<LockupIndex>>,
+    /// The indices of the account's active lockups.
+    pub account_lockups: LookupMap<AccountId, UnorderedSet<LockupIndex>>,
+    /// The account lockups in the previous layout, until all accounts are migrated.
+    pub legacy_account_lockups: Option<LookupMap<AccountId, HashSet<LockupIndex>>>,
 
     /// Account IDs that can create new lockups.
     pub deposit_whitelist: UnorderedSet<AccountId>,
     /// Account IDs that can't claim for some reason
-    pub blacklist: UnorderedSet<AccountId>,
+    pub blacklist: UnorderedMap<AccountId, BlacklistEntry>,
+    /// The panic message for blacklisted accounts.
+    pub blacklist_message: String,
+    /// The lifted freezes by account, in the order they were lifted.
+    pub lifted_freezes: LookupMap<AccountId, Vec<LiftedFreeze>>,
 
     pub owner_id: AccountId,
+    /// The account ID proposed as the next owner, until it accepts the ownership.
+    pub pending_owner_id: Option<AccountId>,
+
+    /// Owner-controlled switches that stop claims, terminations or deposits.
+    pub paused: PauseState,
+
+    /// The prepaid storage of the depositors.
+    pub storage_accounts: LookupMap<AccountId, AccountStorage>,
+    /// The storage charged for lockups, by lockup index.
+    pub lockup_storage: LookupMap<LockupIndex, LockupStorage>,
+    /// The storage used by a single depositor record.
+    pub account_storage_usage: StorageUsage,
+
+    /// If enabled, fully claimed lockups are archived to free the storage.
+    pub compaction_enabled: bool,
+
+    /// The registered schedule shapes by their hash.
+    pub schedules: LookupMap<CryptoHash, Schedule>,
+
+    /// The NEAR held for the NEAR lockups and the withdrawable NEAR balances.
+    pub near_locked_balance: Balance,
+
+    /// How the locked balances count as voting power, if configured.
+    pub voting_power_config: Option<VotingPowerConfig>,
+
+    /// The snapshots of the account's totals by account and token, in the order of changes.
+    pub balance_history: LookupMap<(AccountId, TokenAccountId), Vector<BalanceSnapshot>>,
+
+    /// The staking contracts, that the locked balances can be staked in.
+    pub farm_whitelist: UnorderedSet<AccountId>,
+    /// The staked principal by lockup index.
+    pub lockup_stakes: LookupMap<LockupIndex, LockupStake>,
+
+    /// The account that can claw back the unclaimed balances after the claim deadline.
+    pub treasury_id: Option<AccountId>,
+    /// The contract-wide claim deadline of the lockups without their own deadline.
+    pub claim_period: Option<ClaimPeriod>,
+
+    /// The Merkle-root lockup pools.
+    pub campaigns: Vector<Campaign>,
+    /// The claimed campaign leaves.
+    pub campaign_claims: LookupSet<(CampaignIndex, CryptoHash)>,
+
+    /// The prefunded balances of the voucher pools by depositor and token.
+    pub voucher_pools: LookupMap<(AccountId, TokenAccountId), Balance>,
+    /// The ed25519 keys, that sign the vouchers, by depositor.
+    pub voucher_keys: LookupSet<(AccountId, Vec<u8>)>,
+    /// The redeemed voucher nonces by depositor.
+    pub voucher_nonces: LookupSet<(AccountId, u64)>,
+    /// The balances of the failed claim transfers, that the accounts can withdraw.
+    pub withdrawable: LookupMap<AccountId, HashMap<TokenAccountId, Balance>>,
+    /// If enabled, the failed claim transfers are rolled back to the lockups instead.
+    pub rollback_failed_claims: bool,
+
+    /// The start time of the account's in-flight claim, until its transfers are resolved.
+    pub claim_locks: LookupMap<AccountId, TimestampSec>,
+
+    /// The tokens, that the accounts are known to be registered with, by account.
+    /// Cleared when a transfer of the token to the account fails.
+    pub token_registrations: LookupSet<(AccountId, TokenAccountId)>,
+
+    /// The accounts that can claim on behalf of any account.
+    pub keepers: UnorderedSet<AccountId>,
+    /// The accounts that allowed anyone to claim on their behalf.
+    pub claim_for_accounts: LookupSet<AccountId>,
+    /// The cap of the keeper fee, in basis points of the claimed balance.
+    pub max_keeper_fee_bps: u32,
 }
 
 #[derive(BorshStorageKey, BorshSerialize)]
 pub(crate) enum StorageKey {
     Lockups,
+    /// The legacy account lockups, only read through `legacy_account_lockups`.
+    #[allow(dead_code)]
     AccountLockups,
     DepositWhitelist,
+    /// The legacy blacklist set, only read by the migration.
+    #[allow(dead_code)]
     Blacklist,
+    BlacklistEntries,
+    LegacyLockupsMigrated,
+    LiftedFreezes,
+    StorageAccounts,
+    LockupStorage,
+    AccountLockupIndices,
+    AccountLockupSet {
+        account_hash: CryptoHash,
+    },
+    Schedules,
+    TokenWhitelist,
+    BalanceHistoryAccounts,
+    BalanceHistory {
+        account_token_hash: CryptoHash,
+    },
+    FarmWhitelist,
+    LockupStakes,
+    Campaigns,
+    CampaignClaims,
+    VoucherPools,
+    VoucherKeys,
+    VoucherNonces,
+    Withdrawable,
+    ClaimLocks,
+    TokenRegistrations,
+    Keepers,
+    ClaimForAccounts,
 }
 
 #[near_bindgen]
@@ -88,61 +272,114 @@ impl Contract {
     pub fn new(token_account_id: ValidAccountId, deposit_whitelist: Vec<ValidAccountId>) -> Self {
         let mut deposit_whitelist_set = UnorderedSet::new(StorageKey::DepositWhitelist);
         deposit_whitelist_set.extend(deposit_whitelist.into_iter().map(|a| a.into()));
-        Self {
+        let mut token_whitelist = UnorderedSet::new(StorageKey::TokenWhitelist);
+        token_whitelist.insert(token_account_id.as_ref());
+        let mut this = Self {
             lockups: Vector::new(StorageKey::Lockups),
-            account_lockups: LookupMap::new(StorageKey::AccountLockups),
-            token_account_id: token_account_id.into(),
+            legacy_lockups: None,
+            account_lockups: LookupMap::new(StorageKey::AccountLockupIndices),
+            legacy_account_lockups: None,
+            token_whitelist,
             deposit_whitelist: deposit_whitelist_set,
-            blacklist: UnorderedSet::new(StorageKey::Blacklist),
+            blacklist: UnorderedMap::new(StorageKey::BlacklistEntries),
+            blacklist_message: DEFAULT_BLACKLIST_MESSAGE.to_string(),
+            lifted_freezes: LookupMap::new(StorageKey::LiftedFreezes),
             owner_id: env::predecessor_account_id(),
-        }
+            pending_owner_id: None,
+            paused: PauseState::default(),
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+            lockup_storage: LookupMap::new(StorageKey::LockupStorage),
+            account_storage_usage: 0,
+            compaction_enabled: false,
+            schedules: LookupMap::new(StorageKey::Schedules),
+            near_locked_balance: 0,
+            voting_power_config: None,
+            balance_history: LookupMap::new(StorageKey::BalanceHistoryAccounts),
+            farm_whitelist: UnorderedSet::new(StorageKey::FarmWhitelist),
+            lockup_stakes: LookupMap::new(StorageKey::LockupStakes),
+            treasury_id: None,
+            claim_period: None,
+            campaigns: Vector::new(StorageKey::Campaigns),
+            campaign_claims: LookupSet::new(StorageKey::CampaignClaims),
+            voucher_pools: LookupMap::new(StorageKey::VoucherPools),
+            voucher_keys: LookupSet::new(StorageKey::VoucherKeys),
+            voucher_nonces: LookupSet::new(StorageKey::VoucherNonces),
+            withdrawable: LookupMap::new(StorageKey::Withdrawable),
+            rollback_failed_claims: false,
+            claim_locks: LookupMap::new(StorageKey::ClaimLocks),
+            token_registrations: LookupSet::new(StorageKey::TokenRegistrations),
+            keepers: UnorderedSet::new(StorageKey::Keepers),
+            claim_for_accounts: LookupSet::new(StorageKey::ClaimForAccounts),
+            max_keeper_fee_bps: 0,
+        };
+        this.measure_account_storage_usage();
+        this
     }
 
-    pub fn claim(&mut self) -> PromiseOrValue<WrappedBalance> {
+    /// Claims the unlocked balance of the account's lockups. If `token_account_id` is given,
+    /// only the lockups of this token are claimed.
+    /// The claims are transferred with one `ft_transfer` per token, together with the
+    /// withdrawable balance of the token. Returns the total transferred balance, so it's only
+    /// meaningful for a single token.
+    /// Before the transfers, checks that the account is registered with the claimed tokens.
+    /// The attached deposit, if any, is deposited to the account's storage on the claimed token.
+    #[payable]
+    pub fn claim(
+        &mut self,
+        token_account_id: Option<TokenAccountId>,
+    ) -> PromiseOrValue<WrappedBalance> {
+        self.assert_not_paused(PauseKind::Claim);
         let account_id = env::predecessor_account_id();
-        let lockups = self.internal_get_account_lockups(&account_id);
-
-        if self.blacklist.contains(&account_id) {
-            panic!("Your wallet is facing issues with the tokens claim. To claim your tokens contact us via hq@pembrock.finance");
+        self.assert_not_blacklisted(&account_id);
+        if let Some(token_account_id) = &token_account_id {
+            assert_valid_token_id(token_account_id);
         }
+        self.internal_claim_with_registration_check(account_id, token_account_id, None)
+    }
 
-        if lockups.is_empty() {
+    pub fn terminate(
+        &mut self,
+        lockup_index: LockupIndex,
+        hashed_schedule: Option<Schedule>,
+    ) -> PromiseOrValue<WrappedBalance> {
+        self.assert_not_paused(PauseKind::Terminate);
+        let account_id = env::predecessor_account_id();
+        let beneficiary_id: AccountId = self.internal_unwrap_lockup(lockup_index).account_id.into();
+        self.internal_lift_expired_blacklist(&beneficiary_id);
+        let mut lockup = self.internal_unwrap_lockup(lockup_index);
+        let vesting_timestamp = self.internal_vesting_timestamp(&beneficiary_id);
+        let held_balance = lockup.schedule.total_balance()
+            - lockup.claimed_balance
+            - self.internal_staked_balance(lockup_index);
+        let unvested_balance = lockup.terminate(&account_id, hashed_schedule, vesting_timestamp);
+        let token_account_id = lockup.token_account_id.clone();
+        self.internal_save_lockup(lockup_index, lockup);
+        if unvested_balance == 0 {
             return PromiseOrValue::Value(0.into());
         }
-
-        let mut lockup_claims = vec![];
-        let mut total_unclaimed_balance = 0;
-        for (lockup_index, mut lockup) in lockups {
-            let lockup_claim = lockup.claim(lockup_index);
-            if lockup_claim.unclaimed_balance.0 > 0 {
-                log!(
-                    "Claiming {} form lockup #{}",
-                    lockup_claim.unclaimed_balance.0,
-                    lockup_index
-                );
-                total_unclaimed_balance += lockup_claim.unclaimed_balance.0;
-                self.lockups.replace(lockup_index as _, &lockup);
-                lockup_claims.push(lockup_claim);
-            }
+        self.internal_update_balance_history(&beneficiary_id, &token_account_id, |snapshot| {
+            snapshot.locked_balance -= unvested_balance
+        });
+        // The staked part of the unvested balance is transferred once it's unstaked.
+        let transfer_balance = std::cmp::min(unvested_balance, held_balance);
+        if unvested_balance > transfer_balance {
+            self.internal_terminate_stake(
+                lockup_index,
+                &account_id,
+                unvested_balance - transfer_balance,
+            );
         }
-        log!("Total claim {}", total_unclaimed_balance);
-
-        if total_unclaimed_balance > 0 {
-            ext_fungible_token::ft_transfer(
+        if transfer_balance > 0 {
+            self.internal_transfer(
+                &token_account_id,
                 account_id.clone(),
-                total_unclaimed_balance.into(),
-                Some(format!(
-                    "Claiming unlocked {} balance from {}",
-                    total_unclaimed_balance,
-                    env::current_account_id()
-                )),
-                &self.token_account_id,
-                ONE_YOCTO,
-                GAS_FOR_FT_TRANSFER,
+                transfer_balance,
+                format!("Terminated lockup #{}", lockup_index),
             )
-            .then(ext_self::after_ft_transfer(
+            .then(ext_self::after_lockup_termination(
                 account_id,
-                lockup_claims,
+                token_account_id,
+                transfer_balance.into(),
                 &env::current_account_id(),
                 NO_DEPOSIT,
                 GAS_FOR_AFTER_FT_TRANSFER,
@@ -153,30 +390,69 @@ impl Contract {
         }
     }
 
-    pub fn terminate(
-        &mut self,
-        lockup_index: LockupIndex,
-        hashed_schedule: Option<Schedule>,
-    ) -> PromiseOrValue<WrappedBalance> {
+    /// Cancels the lockup and returns the locked balance to the creator. Can only be called
+    /// by the creator until the schedule starts unlocking, or until the cancellation deadline
+    /// of the lockup.
+    pub fn cancel_lockup(&mut self, lockup_index: LockupIndex) -> PromiseOrValue<WrappedBalance> {
+        self.assert_not_paused(PauseKind::Terminate);
         let account_id = env::predecessor_account_id();
-        let mut lockup = self
-            .lockups
-            .get(lockup_index as _)
-            .expect("Lockup not found");
-        let unvested_balance = lockup.terminate(&account_id, hashed_schedule);
-        self.lockups.replace(lockup_index as _, &lockup);
-        if unvested_balance > 0 {
-            ext_fungible_token::ft_transfer(
+        let mut lockup = self.internal_unwrap_lockup(lockup_index);
+        assert_eq!(
+            lockup.creator_id.as_ref(),
+            Some(&account_id),
+            "Unauthorized"
+        );
+        let beneficiary_id: AccountId = lockup.account_id.clone().into();
+        self.internal_lift_expired_blacklist(&beneficiary_id);
+        // The lockup doesn't unlock while the beneficiary is frozen.
+        let frozen_lockup = self.internal_unwrap_frozen_lockup(lockup_index);
+        let cancellable_until = frozen_lockup.cancellable_until().unwrap();
+        assert!(
+            current_timestamp_sec() < cancellable_until,
+            "The cancellation window has passed"
+        );
+        assert_eq!(
+            self.internal_staked_balance(lockup_index),
+            0,
+            "The lockup balance is staked, unstake it first"
+        );
+        let token_account_id = lockup.token_account_id.clone();
+        // The balance that has already unlocked stays with the beneficiary.
+        let retained_balance = std::cmp::max(
+            lockup.claimed_balance,
+            frozen_lockup
+                .schedule
+                .unlocked_balance(current_timestamp_sec()),
+        );
+        let refund_balance = lockup.schedule.total_balance() - retained_balance;
+        lockup.schedule.terminate(retained_balance);
+        lockup.schedule_id = None;
+        lockup.termination_config = None;
+        let is_finished = lockup.is_finished();
+        self.internal_save_lockup(lockup_index, lockup);
+        if is_finished {
+            self.internal_remove_finished_lockups(&beneficiary_id, vec![lockup_index]);
+        }
+        log!(
+            "Cancelled lockup #{} of {}, refunding {}",
+            lockup_index,
+            beneficiary_id,
+            refund_balance
+        );
+        if refund_balance > 0 {
+            self.internal_update_balance_history(&beneficiary_id, &token_account_id, |snapshot| {
+                snapshot.locked_balance -= refund_balance
+            });
+            self.internal_transfer(
+                &token_account_id,
                 account_id.clone(),
-                unvested_balance.into(),
-                Some(format!("Terminated lockup #{}", lockup_index)),
-                &self.token_account_id,
-                ONE_YOCTO,
-                GAS_FOR_FT_TRANSFER,
+                refund_balance,
+                format!("Cancelled lockup #{}", lockup_index),
             )
             .then(ext_self::after_lockup_termination(
                 account_id,
-                unvested_balance.into(),
+                token_account_id,
+                refund_balance.into(),
                 &env::current_account_id(),
                 NO_DEPOSIT,
                 GAS_FOR_AFTER_FT_TRANSFER,
@@ -201,17 +477,44 @@ impl Contract {
         self.deposit_whitelist.remove(account_id.as_ref());
     }
 
+    /// Allows lockups of the given token. The contract has to be registered with the token.
     #[payable]
-    pub fn add_to_blacklist(&mut self, account_id: ValidAccountId) {
+    pub fn add_to_token_whitelist(&mut self, token_account_id: ValidAccountId) {
         assert_one_yocto();
         self.assert_owner();
-        self.blacklist.insert(account_id.as_ref());
+        assert_ne!(
+            token_account_id.as_ref(),
+            NEAR_TOKEN_ID,
+            "Native NEAR lockups are created with create_near_lockup"
+        );
+        self.token_whitelist.insert(token_account_id.as_ref());
     }
 
+    /// Stops accepting new lockups of the given token. The existing lockups can still be claimed.
     #[payable]
-    pub fn remove_to_blacklist(&mut self, account_id: ValidAccountId) {
+    pub fn remove_from_token_whitelist(&mut self, token_account_id: ValidAccountId) {
         assert_one_yocto();
         self.assert_owner();
-        self.blacklist.remove(account_id.as_ref());
+        self.token_whitelist.remove(token_account_id.as_ref());
+    }
+
+    #[payable]
+    pub fn set_compaction_enabled(&mut self, enabled: bool) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.compaction_enabled = enabled;
+    }
+
+    /// Archives the given fully claimed lockups. Returns the number of archived lockups.
+    pub fn compact_lockups(&mut self, indices: Vec<LockupIndex>) -> u32 {
+        assert!(self.compaction_enabled, "Compaction is disabled");
+        let mut num_archived = 0;
+        for index in indices {
+            if self.internal_archive_lockup(index) {
+                num_archived += 1;
+            }
+        }
+        log!("Archived {} lockups", num_archived);
+        num_archived
     }
 }
@@ -3,6 +3,9 @@ use std::convert::TryInto;
 
 pub type LockupIndex = u32;
 
+pub const MAX_LOCKUP_MEMO_LENGTH: usize = 256;
+pub const MAX_LOCKUP_CATEGORY_LENGTH: usize = 64;
+
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
@@ -12,6 +15,33 @@ pub struct LockupClaim {
     pub is_final: bool,
 }
 
+/// The lockup claims of a single token, that are transferred together.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct TokenClaims {
+    pub token_account_id: TokenAccountId,
+    pub lockup_claims: Vec<LockupClaim>,
+    /// The withdrawable balance, that is transferred together with the claims.
+    #[serde(default, with = "u128_dec_format")]
+    pub pending_balance: Balance,
+    /// The keeper fee, that is deducted from the transfer.
+    #[serde(default, with = "u128_dec_format")]
+    pub keeper_fee: Balance,
+}
+
+impl TokenClaims {
+    /// The transferred balance of the claims, without the keeper fee.
+    pub fn transfer_amount(&self) -> Balance {
+        self.lockup_claims
+            .iter()
+            .map(|lockup_claim| lockup_claim.unclaimed_balance.0)
+            .sum::<Balance>()
+            + self.pending_balance
+            - self.keeper_fee
+    }
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 #[cfg_attr(
@@ -20,34 +50,153 @@ pub struct LockupClaim {
 )]
 pub struct Lockup {
     pub account_id: ValidAccountId,
+    /// The token that is locked. It's set to the transferred token on deposit.
+    #[serde(skip_deserializing)]
+    pub token_account_id: TokenAccountId,
+    /// The lockup schedule. Should be omitted if `schedule_id` is given.
+    #[serde(default)]
     pub schedule: Schedule,
+    /// The ID of the registered schedule shape. The schedule is resolved from the shape when
+    /// the lockup is loaded, and only the ID is stored while the schedule is unchanged.
+    #[borsh_skip]
+    #[serde(default)]
+    pub schedule_id: Option<Base58CryptoHash>,
 
     #[serde(default)]
     #[serde(with = "u128_dec_format")]
     pub claimed_balance: Balance,
     /// An optional configuration that allows vesting/lockup termination.
     pub termination_config: Option<TerminationConfig>,
+
+    /// The account ID that funded the lockup. Unknown for the lockups created before it was
+    /// recorded.
+    #[serde(skip_deserializing)]
+    pub creator_id: Option<AccountId>,
+    /// The creation timestamp. Unknown for the lockups created before it was recorded.
+    #[serde(skip_deserializing)]
+    pub created_at: Option<TimestampSec>,
+    /// An optional note about the lockup.
+    pub memo: Option<String>,
+    /// An optional category of the lockup, for example "seed", "team" or "advisor".
+    pub category: Option<String>,
+    /// The timestamp until which the creator can cancel the lockup. It can't be after the
+    /// schedule starts unlocking, which is also the default.
+    pub cancellation_deadline: Option<TimestampSec>,
+    /// The timestamp after which the treasury can claw back the unclaimed balance. If omitted,
+    /// the contract-wide claim period applies.
+    pub claim_deadline: Option<TimestampSec>,
+    /// The number of the account's lifted freezes, that the schedule is delayed by.
+    #[serde(skip)]
+    pub num_applied_freezes: u32,
+}
+
+/// A fully claimed lockup, that only keeps the summary to free the storage.
+#[derive(BorshDeserialize, BorshSerialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub struct ArchivedLockup {
+    pub account_id: ValidAccountId,
+    pub token_account_id: TokenAccountId,
+    pub total_balance: Balance,
+    /// The timestamp of the last checkpoint of the schedule.
+    pub finished_at: TimestampSec,
+    pub creator_id: Option<AccountId>,
+    pub created_at: Option<TimestampSec>,
+    pub category: Option<String>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+pub enum StoredLockup {
+    Lockup(Lockup),
+    Archived(ArchivedLockup),
+    /// The lockup with a registered schedule. The schedule of the inner lockup is empty.
+    Registered {
+        schedule_id: CryptoHash,
+        total_balance: Balance,
+        lockup: Lockup,
+    },
+}
+
+impl From<Lockup> for StoredLockup {
+    fn from(mut lockup: Lockup) -> Self {
+        match lockup.schedule_id.take() {
+            Some(schedule_id) => {
+                let total_balance = lockup.schedule.total_balance();
+                lockup.schedule = Schedule::default();
+                Self::Registered {
+                    schedule_id: schedule_id.into(),
+                    total_balance,
+                    lockup,
+                }
+            }
+            None => Self::Lockup(lockup),
+        }
+    }
 }
 
 impl Lockup {
-    pub fn new_unlocked(account_id: AccountId, total_balance: Balance) -> Self {
+    pub fn new_unlocked(
+        account_id: AccountId,
+        token_account_id: TokenAccountId,
+        total_balance: Balance,
+    ) -> Self {
         Self {
             account_id: account_id.try_into().unwrap(),
+            token_account_id,
             schedule: Schedule::new_unlocked(total_balance),
+            schedule_id: None,
             claimed_balance: 0,
             termination_config: None,
+            creator_id: None,
+            created_at: None,
+            memo: None,
+            category: None,
+            cancellation_deadline: None,
+            claim_deadline: None,
+            num_applied_freezes: 0,
         }
     }
 
-    pub fn claim(&mut self, index: LockupIndex) -> LockupClaim {
+    /// Claims the unlocked balance, except the staked balance that isn't in the contract.
+    pub fn claim(&mut self, index: LockupIndex, staked_balance: Balance) -> LockupClaim {
         let unlocked_balance = self.schedule.unlocked_balance(current_timestamp_sec());
         assert!(unlocked_balance >= self.claimed_balance, "Invariant");
-        let unclaimed_balance = unlocked_balance - self.claimed_balance;
-        self.claimed_balance = unlocked_balance;
+        let claimable_balance = std::cmp::min(
+            unlocked_balance,
+            self.schedule.total_balance() - staked_balance,
+        );
+        assert!(claimable_balance >= self.claimed_balance, "Invariant");
+        let unclaimed_balance = claimable_balance - self.claimed_balance;
+        self.claimed_balance = claimable_balance;
         LockupClaim {
             index,
             unclaimed_balance: unclaimed_balance.into(),
-            is_final: unlocked_balance == self.schedule.total_balance(),
+            is_final: claimable_balance == self.schedule.total_balance(),
+        }
+    }
+
+    /// Returns the timestamp until which the creator can cancel the lockup, if the creator
+    /// is known.
+    pub fn cancellable_until(&self) -> Option<TimestampSec> {
+        self.creator_id.as_ref().map(|_| {
+            self.cancellation_deadline
+                .unwrap_or_else(|| self.schedule.unlock_start())
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.claimed_balance == self.schedule.total_balance()
+    }
+
+    pub fn archive(&self) -> ArchivedLockup {
+        ArchivedLockup {
+            account_id: self.account_id.clone(),
+            token_account_id: self.token_account_id.clone(),
+            total_balance: self.schedule.total_balance(),
+            finished_at: self.schedule.0.last().unwrap().timestamp,
+            creator_id: self.creator_id.clone(),
+            created_at: self.created_at,
+            category: self.category.clone(),
         }
     }
 
@@ -57,6 +206,27 @@ impl Lockup {
             "The initial lockup claimed balance should be 0"
         );
         self.schedule.assert_valid(total_balance);
+        if let Some(memo) = &self.memo {
+            assert!(memo.len() <= MAX_LOCKUP_MEMO_LENGTH, "The memo is too long");
+        }
+        if let Some(claim_deadline) = self.claim_deadline {
+            assert!(
+                claim_deadline >= self.schedule.0.last().unwrap().timestamp,
+                "The claim deadline should be after the schedule ends"
+            );
+        }
+        if let Some(cancellation_deadline) = self.cancellation_deadline {
+            assert!(
+                cancellation_deadline <= self.schedule.unlock_start(),
+                "The cancellation deadline should be before the schedule starts unlocking"
+            );
+        }
+        if let Some(category) = &self.category {
+            assert!(
+                category.len() <= MAX_LOCKUP_CATEGORY_LENGTH,
+                "The category is too long"
+            );
+        }
 
         if let Some(termination_config) = &self.termination_config {
             match &termination_config.vesting_schedule {
@@ -1,41 +1,314 @@
 use crate::*;
-use near_contract_standards::upgrade::Ownable;
 
+/// The lockup layout before the token ID and the creation details were stored per lockup.
 #[derive(BorshDeserialize, BorshSerialize)]
-pub struct OldContract {
+pub struct OldLockup {
+    pub account_id: ValidAccountId,
+    pub schedule: Schedule,
+    pub claimed_balance: Balance,
+    pub termination_config: Option<TerminationConfig>,
+}
+
+impl OldLockup {
+    pub fn into_lockup(self, token_account_id: TokenAccountId) -> Lockup {
+        Lockup {
+            account_id: self.account_id,
+            token_account_id,
+            schedule: self.schedule,
+            schedule_id: None,
+            claimed_balance: self.claimed_balance,
+            termination_config: self.termination_config,
+            creator_id: None,
+            created_at: None,
+            memo: None,
+            category: None,
+            cancellation_deadline: None,
+            claim_deadline: None,
+            num_applied_freezes: 0,
+        }
+    }
+}
+
+/// The lockups in the layout before `StoredLockup`. They are migrated in place, either by
+/// `migrate_lockups` in the order of indices, or on the first change of the lockup.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyLockups {
+    /// The legacy lockups, sharing the storage prefix with `Contract::lockups`.
+    pub lockups: Vector<OldLockup>,
+    /// The token of all legacy lockups.
     pub token_account_id: TokenAccountId,
-    pub lockups: Vector<Lockup>,
-    pub account_lockups: LookupMap<AccountId, HashSet<LockupIndex>>,
-    pub deposit_whitelist: UnorderedSet<AccountId>,
+    /// The lockups before this index are migrated.
+    pub next_index: LockupIndex,
+    /// The lockups from `next_index`, that were migrated by a change.
+    pub migrated_indices: LookupSet<LockupIndex>,
+    /// The time of the upgrade. The balance history of the legacy accounts starts at it.
+    pub migrated_at: TimestampSec,
 }
 
-#[near_bindgen]
-impl Ownable for Contract {
-    fn get_owner(&self) -> AccountId {
-        self.owner_id.clone()
+impl LegacyLockups {
+    /// Returns the lockup, if it's still in the legacy layout.
+    pub fn get(&self, index: LockupIndex) -> Option<Lockup> {
+        if index < self.next_index
+            || index as u64 >= self.lockups.len()
+            || self.migrated_indices.contains(&index)
+        {
+            return None;
+        }
+        self.lockups
+            .get(index as _)
+            .map(|lockup| lockup.into_lockup(self.token_account_id.clone()))
     }
 
-    fn set_owner(&mut self, owner: AccountId) {
-        self.assert_owner();
-        self.owner_id = owner;
+    pub fn mark_migrated(&mut self, index: LockupIndex) {
+        if index >= self.next_index && (index as u64) < self.lockups.len() {
+            self.migrated_indices.insert(&index);
+        }
+    }
+
+    pub fn num_remaining(&self) -> u32 {
+        self.lockups.len() as u32 - self.next_index
     }
 }
 
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct OldContract {
+    pub token_account_id: TokenAccountId,
+    pub lockups: Vector<OldLockup>,
+    pub account_lockups: LookupMap<AccountId, HashSet<LockupIndex>>,
+    pub deposit_whitelist: UnorderedSet<AccountId>,
+    pub blacklist: UnorderedSet<AccountId>,
+    pub owner_id: AccountId,
+}
+
 #[near_bindgen]
 impl Contract {
     /// Migration function for contract upgrade
     #[init(ignore_state)]
     #[private]
-    pub fn migrate(owner_id: Option<AccountId>) -> Self {
-        let contract: OldContract = env::state_read().unwrap_or_else(|| panic!("Not initialized"));
+    pub fn migrate() -> Self {
+        let mut contract: OldContract =
+            env::state_read().unwrap_or_else(|| panic!("Not initialized"));
+
+        let blacklisted_at = current_timestamp_sec();
+        let mut blacklist = UnorderedMap::new(StorageKey::BlacklistEntries);
+        for account_id in contract.blacklist.iter() {
+            blacklist.insert(
+                &account_id,
+                &BlacklistEntry {
+                    reason: String::new(),
+                    blacklisted_at,
+                    expires_at: None,
+                    freeze: false,
+                },
+            );
+        }
+        contract.blacklist.clear();
+
+        // The lockups are stored as `StoredLockup` with the token ID now. The new vector covers
+        // the same elements, since the vector state is only its length and storage prefix,
+        // and the elements are rewritten lazily.
+        let lockups: Vector<StoredLockup> =
+            Vector::try_from_slice(&contract.lockups.try_to_vec().unwrap()).unwrap();
+        let legacy_lockups = if contract.lockups.is_empty() {
+            None
+        } else {
+            Some(LegacyLockups {
+                lockups: contract.lockups,
+                token_account_id: contract.token_account_id.clone(),
+                next_index: 0,
+                migrated_indices: LookupSet::new(StorageKey::LegacyLockupsMigrated),
+                migrated_at: current_timestamp_sec(),
+            })
+        };
+
+        let mut token_whitelist = UnorderedSet::new(StorageKey::TokenWhitelist);
+        token_whitelist.insert(&contract.token_account_id);
 
-        Self {
-            token_account_id: contract.token_account_id,
-            lockups: contract.lockups,
-            account_lockups: contract.account_lockups,
+        let mut this = Self {
+            token_whitelist,
+            lockups,
+            legacy_lockups,
+            account_lockups: LookupMap::new(StorageKey::AccountLockupIndices),
+            legacy_account_lockups: Some(contract.account_lockups),
             deposit_whitelist: contract.deposit_whitelist,
-            blacklist: UnorderedSet::new(StorageKey::Blacklist),
-            owner_id: owner_id.unwrap_or_else(|| env::predecessor_account_id()),
+            blacklist,
+            blacklist_message: DEFAULT_BLACKLIST_MESSAGE.to_string(),
+            lifted_freezes: LookupMap::new(StorageKey::LiftedFreezes),
+            owner_id: contract.owner_id,
+            pending_owner_id: None,
+            paused: PauseState::default(),
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+            lockup_storage: LookupMap::new(StorageKey::LockupStorage),
+            account_storage_usage: 0,
+            compaction_enabled: false,
+            schedules: LookupMap::new(StorageKey::Schedules),
+            near_locked_balance: 0,
+            voting_power_config: None,
+            balance_history: LookupMap::new(StorageKey::BalanceHistoryAccounts),
+            farm_whitelist: UnorderedSet::new(StorageKey::FarmWhitelist),
+            lockup_stakes: LookupMap::new(StorageKey::LockupStakes),
+            treasury_id: None,
+            claim_period: None,
+            campaigns: Vector::new(StorageKey::Campaigns),
+            campaign_claims: LookupSet::new(StorageKey::CampaignClaims),
+            voucher_pools: LookupMap::new(StorageKey::VoucherPools),
+            voucher_keys: LookupSet::new(StorageKey::VoucherKeys),
+            voucher_nonces: LookupSet::new(StorageKey::VoucherNonces),
+            withdrawable: LookupMap::new(StorageKey::Withdrawable),
+            rollback_failed_claims: false,
+            claim_locks: LookupMap::new(StorageKey::ClaimLocks),
+            token_registrations: LookupSet::new(StorageKey::TokenRegistrations),
+            keepers: UnorderedSet::new(StorageKey::Keepers),
+            claim_for_accounts: LookupSet::new(StorageKey::ClaimForAccounts),
+            max_keeper_fee_bps: 0,
+        };
+        this.measure_account_storage_usage();
+        this
+    }
+
+    /// Migrates up to `limit` of the next lockups from the legacy layout, together with their
+    /// accounts. The lockups are also migrated lazily on their first change. Returns the number
+    /// of the remaining lockups.
+    pub fn migrate_lockups(&mut self, limit: u32) -> u32 {
+        for _ in 0..limit {
+            if !self.internal_migrate_next_lockup() {
+                break;
+            }
+        }
+        self.get_num_legacy_lockups()
+    }
+
+    /// Returns the number of the lockups, that `migrate_lockups` hasn't reached yet.
+    pub fn get_num_legacy_lockups(&self) -> u32 {
+        self.legacy_lockups
+            .as_ref()
+            .map(|legacy_lockups| legacy_lockups.num_remaining())
+            .unwrap_or(0)
+    }
+
+    /// Moves the lockup indices of the given accounts from the legacy layout.
+    /// The accounts are also migrated lazily on the first change of their lockups.
+    /// Returns the number of migrated accounts.
+    pub fn migrate_account_lockups(&mut self, account_ids: Vec<ValidAccountId>) -> u32 {
+        let mut num_migrated = 0;
+        for account_id in account_ids {
+            if self.internal_migrate_account_lockups(account_id.as_ref()) {
+                num_migrated += 1;
+            }
+        }
+        num_migrated
+    }
+
+    /// Drops the legacy account lockups layout, once all accounts are migrated. The accounts
+    /// of all legacy lockups are migrated by `migrate_lockups`.
+    #[payable]
+    pub fn finish_account_lockups_migration(&mut self) {
+        assert_one_yocto();
+        self.assert_owner();
+        assert_eq!(
+            self.get_num_legacy_lockups(),
+            0,
+            "Not all accounts are migrated, call migrate_lockups first"
+        );
+        self.legacy_account_lockups = None;
+    }
+}
+
+impl Contract {
+    /// Migrates the next legacy lockup, unless it was migrated by a change. Drops the legacy
+    /// layout after the last one. Returns false if there is nothing to migrate.
+    fn internal_migrate_next_lockup(&mut self) -> bool {
+        let index = match self.legacy_lockups.as_ref() {
+            Some(legacy_lockups) => legacy_lockups.next_index,
+            None => return false,
+        };
+        if let Some(lockup) = self.legacy_lockups.as_ref().unwrap().get(index) {
+            self.internal_migrate_legacy_lockup(index, lockup);
+        }
+        let legacy_lockups = self.legacy_lockups.as_mut().unwrap();
+        legacy_lockups.migrated_indices.remove(&index);
+        legacy_lockups.next_index += 1;
+        if legacy_lockups.num_remaining() == 0 {
+            self.legacy_lockups = None;
+        }
+        true
+    }
+
+    /// Migrates the legacy lockup together with its account. Every legacy account has a legacy
+    /// lockup, so all accounts are migrated once all lockups are.
+    pub(crate) fn internal_migrate_legacy_lockup(&mut self, index: LockupIndex, lockup: Lockup) {
+        let account_id: AccountId = lockup.account_id.clone().into();
+        self.internal_migrate_account_lockups(&account_id);
+        // The account migration converts the lockups of the account, so the lockup is still
+        // legacy only if it was finished and removed from the account before the upgrade.
+        if self.legacy_lockups.as_ref().unwrap().get(index).is_some() {
+            self.internal_update_balance_history(
+                &account_id,
+                &lockup.token_account_id,
+                |snapshot| {
+                    snapshot.locked_balance +=
+                        lockup.schedule.total_balance() - lockup.claimed_balance;
+                    snapshot.claimed_balance += lockup.claimed_balance;
+                },
+            );
+            self.internal_convert_legacy_lockup(index, lockup);
+        }
+    }
+
+    /// Rewrites the legacy lockup in the new layout.
+    fn internal_convert_legacy_lockup(&mut self, index: LockupIndex, lockup: Lockup) {
+        self.legacy_lockups.as_mut().unwrap().mark_migrated(index);
+        self.lockups.replace_raw(
+            index as _,
+            &StoredLockup::Lockup(lockup).try_to_vec().unwrap(),
+        );
+    }
+
+    /// Returns the totals of the legacy account's lockups, as of the upgrade.
+    pub(crate) fn internal_legacy_balance_snapshot(
+        &self,
+        indices: &HashSet<LockupIndex>,
+    ) -> Option<BalanceSnapshot> {
+        let migrated_at = self.legacy_lockups.as_ref()?.migrated_at;
+        let mut snapshot = BalanceSnapshot {
+            timestamp: migrated_at,
+            ..Default::default()
+        };
+        for &index in indices {
+            let lockup = self.internal_unwrap_lockup(index);
+            snapshot.locked_balance += lockup.schedule.total_balance() - lockup.claimed_balance;
+            snapshot.claimed_balance += lockup.claimed_balance;
+        }
+        Some(snapshot)
+    }
+
+    /// Starts the balance history of the account with the totals of its legacy lockups and
+    /// converts the lockups. Called once the account is removed from the legacy layout, before
+    /// any of its legacy lockups is changed.
+    pub(crate) fn internal_seed_balance_history(
+        &mut self,
+        account_id: &AccountId,
+        indices: HashSet<LockupIndex>,
+    ) {
+        let snapshot = match self.internal_legacy_balance_snapshot(&indices) {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        let token_account_id = self
+            .legacy_lockups
+            .as_ref()
+            .unwrap()
+            .token_account_id
+            .clone();
+        for &index in &indices {
+            if let Some(lockup) = self.legacy_lockups.as_ref().unwrap().get(index) {
+                self.internal_convert_legacy_lockup(index, lockup);
+            }
         }
+        // The history of the legacy token is empty, since every update migrates the account.
+        let mut history = self.internal_get_balance_history(account_id, &token_account_id);
+        history.push(&snapshot);
+        self.balance_history
+            .insert(&(account_id.clone(), token_account_id), &history);
     }
 }
@@ -0,0 +1,44 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Creates a lockup of the attached NEAR. The lockup storage is charged to the depositor's
+    /// storage balance, so the attached deposit is the total balance of the lockup.
+    /// If `notify` is true, the beneficiary is notified with `on_lockup_created`.
+    #[payable]
+    pub fn create_near_lockup(
+        &mut self,
+        lockup: Lockup,
+        notify: Option<bool>,
+        notify_msg: Option<String>,
+    ) -> LockupIndex {
+        let depositor_id = env::predecessor_account_id();
+        self.assert_deposit_whitelist(&depositor_id);
+        self.assert_not_paused(PauseKind::Deposit);
+        let mut lockup = lockup;
+        lockup.token_account_id = NEAR_TOKEN_ID.to_string();
+        let index = self.internal_create_lockup(&depositor_id, lockup, env::attached_deposit());
+        self.near_locked_balance += env::attached_deposit();
+        if notify.unwrap_or(false) {
+            self.internal_notify_lockup_created(index, notify_msg);
+        }
+        index
+    }
+
+    /// Returns the NEAR held for the NEAR lockups and the withdrawable NEAR balances.
+    pub fn get_near_locked_balance(&self) -> WrappedBalance {
+        self.near_locked_balance.into()
+    }
+}
+
+impl Contract {
+    /// Asserts that the NEAR balance, that isn't used for the storage, still covers the NEAR
+    /// lockups. Checked after every NEAR transfer out of the contract.
+    pub(crate) fn assert_near_locked_balance(&self) {
+        let storage_cost = Balance::from(env::storage_usage()) * env::storage_byte_cost();
+        assert!(
+            env::account_balance().saturating_sub(storage_cost) >= self.near_locked_balance,
+            "The NEAR balance doesn't cover the NEAR lockups"
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use crate::view::LockupView;
+use crate::*;
+
+const GAS_FOR_ON_LOCKUP_CREATED: Gas = 20_000_000_000_000;
+const GAS_FOR_AFTER_LOCKUP_CREATED: Gas = 5_000_000_000_000;
+
+/// The interface of the beneficiary contracts, that are notified about their new lockups.
+#[ext_contract(ext_lockup_receiver)]
+pub trait LockupReceiver {
+    fn on_lockup_created(&mut self, lockup_index: LockupIndex, lockup: LockupView, msg: String);
+}
+
+/// The message of the lockup deposit. Besides the lockup itself, it can ask to notify the
+/// beneficiary with `on_lockup_created`.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+pub struct DepositMessage {
+    #[serde(flatten)]
+    pub lockup: Lockup,
+    #[serde(default)]
+    pub notify: bool,
+    /// The message passed to `on_lockup_created`.
+    pub notify_msg: Option<String>,
+}
+
+/// The `ft_on_transfer` message, that either creates a lockup, funds a campaign or funds the
+/// depositor's voucher pool.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(untagged)]
+pub enum FtOnTransferMessage {
+    Campaign { campaign: NewCampaign },
+    VoucherPool { voucher_pool: VoucherPoolDeposit },
+    Lockup(Box<DepositMessage>),
+}
+
+impl Contract {
+    /// Calls `on_lockup_created` on the beneficiary. A failure doesn't revert the lockup, and
+    /// is only logged.
+    pub(crate) fn internal_notify_lockup_created(
+        &self,
+        lockup_index: LockupIndex,
+        msg: Option<String>,
+    ) {
+        let lockup = self.internal_unwrap_lockup(lockup_index);
+        let account_id: AccountId = lockup.account_id.clone().into();
+        ext_lockup_receiver::on_lockup_created(
+            lockup_index,
+            self.internal_lockup_view(lockup),
+            msg.unwrap_or_default(),
+            &account_id,
+            NO_DEPOSIT,
+            GAS_FOR_ON_LOCKUP_CREATED,
+        )
+        .then(ext_self::after_lockup_created_notification(
+            lockup_index,
+            account_id,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_AFTER_LOCKUP_CREATED,
+        ));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::*;
+
+#[near_bindgen]
+impl Ownable for Contract {
+    fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    /// Doesn't change the owner directly, but proposes the new owner, who has to call
+    /// `accept_ownership` to finish the transfer.
+    fn set_owner(&mut self, owner: AccountId) {
+        self.assert_owner();
+        self.internal_propose_owner(owner);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Proposes a new owner. The ownership is only transferred once the proposed account
+    /// calls `accept_ownership`.
+    #[payable]
+    pub fn propose_owner(&mut self, new_owner_id: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.internal_propose_owner(new_owner_id.into());
+    }
+
+    #[payable]
+    pub fn accept_ownership(&mut self) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let pending_owner_id = self
+            .pending_owner_id
+            .take()
+            .expect("No pending owner proposal");
+        assert_eq!(pending_owner_id, account_id, "Not the pending owner");
+        let old_owner_id = std::mem::replace(&mut self.owner_id, pending_owner_id);
+        event::emit(Event::OwnershipTransferred {
+            old_owner_id,
+            new_owner_id: account_id,
+        });
+    }
+
+    #[payable]
+    pub fn cancel_owner_proposal(&mut self) {
+        assert_one_yocto();
+        self.assert_owner();
+        let pending_owner_id = self
+            .pending_owner_id
+            .take()
+            .expect("No pending owner proposal");
+        event::emit(Event::OwnerProposalCancelled {
+            owner_id: self.owner_id.clone(),
+            pending_owner_id,
+        });
+    }
+
+    pub fn get_pending_owner(&self) -> Option<AccountId> {
+        self.pending_owner_id.clone()
+    }
+}
+
+impl Contract {
+    fn internal_propose_owner(&mut self, pending_owner_id: AccountId) {
+        assert!(
+            env::is_valid_account_id(pending_owner_id.as_bytes()),
+            "Invalid account ID"
+        );
+        assert_ne!(
+            pending_owner_id, self.owner_id,
+            "The account is already the owner"
+        );
+        self.pending_owner_id = Some(pending_owner_id.clone());
+        event::emit(Event::OwnerProposed {
+            owner_id: self.owner_id.clone(),
+            pending_owner_id,
+        });
+    }
+}
@@ -0,0 +1,76 @@
+use crate::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub enum PauseKind {
+    /// Pauses `claim`.
+    Claim,
+    /// Pauses `terminate`, `cancel_lockup`, `claw_back` and `refund_campaign`.
+    Terminate,
+    /// Pauses new lockups from `ft_on_transfer`. The deposits are refunded.
+    Deposit,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Default, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Deserialize))]
+pub struct PauseState {
+    pub claim: bool,
+    pub terminate: bool,
+    pub deposit: bool,
+}
+
+impl PauseState {
+    pub fn is_paused(&self, kind: PauseKind) -> bool {
+        match kind {
+            PauseKind::Claim => self.claim,
+            PauseKind::Terminate => self.terminate,
+            PauseKind::Deposit => self.deposit,
+        }
+    }
+
+    fn set_paused(&mut self, kind: PauseKind, paused: bool) {
+        match kind {
+            PauseKind::Claim => self.claim = paused,
+            PauseKind::Terminate => self.terminate = paused,
+            PauseKind::Deposit => self.deposit = paused,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    #[payable]
+    pub fn pause(&mut self, kind: PauseKind) {
+        assert_one_yocto();
+        self.assert_owner();
+        assert!(!self.paused.is_paused(kind), "Already paused");
+        self.paused.set_paused(kind, true);
+        event::emit(Event::Paused { kind });
+    }
+
+    #[payable]
+    pub fn unpause(&mut self, kind: PauseKind) {
+        assert_one_yocto();
+        self.assert_owner();
+        assert!(self.paused.is_paused(kind), "Not paused");
+        self.paused.set_paused(kind, false);
+        event::emit(Event::Unpaused { kind });
+    }
+
+    pub fn get_pause_state(&self) -> PauseState {
+        self.paused.clone()
+    }
+
+    pub fn is_paused(&self, kind: PauseKind) -> bool {
+        self.paused.is_paused(kind)
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_not_paused(&self, kind: PauseKind) {
+        assert!(!self.paused.is_paused(kind), "Paused");
+    }
+}
@@ -10,7 +10,7 @@ pub struct Checkpoint {
     pub balance: Balance,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Default)]
 #[serde(crate = "near_sdk::serde")]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
 pub struct Schedule(pub Vec<Checkpoint>);
@@ -99,6 +99,17 @@ impl Schedule {
             .as_u128()
     }
 
+    /// Returns the timestamp the schedule starts unlocking after, which is the timestamp of
+    /// the last checkpoint with 0 balance.
+    pub fn unlock_start(&self) -> TimestampSec {
+        self.0
+            .iter()
+            .take_while(|checkpoint| checkpoint.balance == 0)
+            .last()
+            .map(|checkpoint| checkpoint.timestamp)
+            .unwrap_or(0)
+    }
+
     pub fn total_balance(&self) -> Balance {
         self.0.last().unwrap().balance
     }
@@ -136,6 +147,46 @@ impl Schedule {
         unreachable!();
     }
 
+    /// Delays the part of the schedule after the given timestamp by the given duration.
+    /// The unlocked balance doesn't change from `from` until `from + duration`.
+    pub fn shift(&mut self, from: TimestampSec, duration: TimestampSec) {
+        if duration == 0 {
+            return;
+        }
+        let index = self
+            .0
+            .partition_point(|checkpoint| checkpoint.timestamp <= from);
+        if index == self.0.len() {
+            // Fully unlocked before `from`.
+            return;
+        }
+        let index = if index == 0 {
+            // Not started at `from`, delaying the whole schedule.
+            0
+        } else {
+            let balance = self.unlocked_balance(from);
+            let mut checkpoints = vec![Checkpoint {
+                timestamp: from + duration,
+                balance,
+            }];
+            if self.0[index - 1].timestamp < from {
+                checkpoints.insert(
+                    0,
+                    Checkpoint {
+                        timestamp: from,
+                        balance,
+                    },
+                );
+            }
+            let num_inserted = checkpoints.len();
+            self.0.splice(index..index, checkpoints);
+            index + num_inserted
+        };
+        for checkpoint in &mut self.0[index..] {
+            checkpoint.timestamp += duration;
+        }
+    }
+
     pub fn hash(&self) -> CryptoHash {
         let value_hash = env::sha256(&self.try_to_vec().unwrap());
         let mut res = CryptoHash::default();
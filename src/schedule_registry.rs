@@ -0,0 +1,57 @@
+use crate::*;
+
+impl Schedule {
+    /// Scales the schedule shape to the given total balance.
+    pub fn scale(&self, total_balance: Balance) -> Self {
+        let shape_total_balance = self.total_balance();
+        Self(
+            self.0
+                .iter()
+                .map(|checkpoint| Checkpoint {
+                    timestamp: checkpoint.timestamp,
+                    balance: (U256::from(checkpoint.balance) * U256::from(total_balance)
+                        / U256::from(shape_total_balance))
+                    .as_u128(),
+                })
+                .collect(),
+        )
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Registers the schedule shape, so lockups can reference it by `schedule_id` instead of
+    /// storing their own checkpoints. The balances of the shape are scaled to the total
+    /// balance of the lockup. Returns the schedule ID, which is the hash of the shape.
+    #[payable]
+    pub fn register_schedule(&mut self, schedule: Schedule) -> Base58CryptoHash {
+        assert_one_yocto();
+        self.assert_owner();
+        let total_balance = schedule.total_balance();
+        assert!(total_balance > 0, "The total balance should be positive");
+        schedule.assert_valid(total_balance);
+        let schedule_id = schedule.hash();
+        assert!(
+            self.schedules.insert(&schedule_id, &schedule).is_none(),
+            "The schedule is already registered"
+        );
+        schedule_id.into()
+    }
+
+    pub fn get_registered_schedule(&self, schedule_id: Base58CryptoHash) -> Option<Schedule> {
+        self.schedules.get(&schedule_id.into())
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_resolve_schedule(
+        &self,
+        schedule_id: &CryptoHash,
+        total_balance: Balance,
+    ) -> Schedule {
+        self.schedules
+            .get(schedule_id)
+            .expect("Schedule is not registered")
+            .scale(total_balance)
+    }
+}
@@ -0,0 +1,230 @@
+use crate::*;
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::{Promise, StorageUsage};
+
+/// The prepaid storage of a depositor.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct AccountStorage {
+    /// The total attached deposit.
+    pub deposit: Balance,
+    /// The number of bytes used by the lockups created by this account.
+    pub used_bytes: StorageUsage,
+}
+
+/// The storage that was charged for a lockup.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LockupStorage {
+    /// The account ID that paid for the storage.
+    pub account_id: AccountId,
+    /// The number of bytes that are still charged to the account.
+    pub used_bytes: StorageUsage,
+}
+
+#[near_bindgen]
+impl StorageManagement for Contract {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<ValidAccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id: AccountId = account_id
+            .map(|a| a.into())
+            .unwrap_or_else(env::predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+        let min_balance = self.storage_balance_bounds().min.0;
+        let mut account_storage = self.storage_accounts.get(&account_id);
+        let refund = match account_storage.as_mut() {
+            Some(_) if registration_only => amount,
+            Some(account_storage) => {
+                account_storage.deposit += amount;
+                0
+            }
+            None => {
+                assert!(
+                    amount >= min_balance,
+                    "The attached deposit is less than the minimum storage balance"
+                );
+                let deposit = if registration_only {
+                    min_balance
+                } else {
+                    amount
+                };
+                account_storage = Some(AccountStorage {
+                    deposit,
+                    used_bytes: 0,
+                });
+                amount - deposit
+            }
+        };
+        self.storage_accounts
+            .insert(&account_id, account_storage.as_ref().unwrap());
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let available = self
+            .internal_storage_balance_of(&account_id)
+            .expect("The account is not registered")
+            .available
+            .0;
+        let amount = amount.map(|a| a.0).unwrap_or(available);
+        assert!(
+            amount <= available,
+            "The amount is greater than the available storage balance"
+        );
+        if amount > 0 {
+            let mut account_storage = self.storage_accounts.get(&account_id).unwrap();
+            account_storage.deposit -= amount;
+            self.storage_accounts.insert(&account_id, &account_storage);
+            Promise::new(account_id.clone()).transfer(amount);
+            self.assert_near_locked_balance();
+        }
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    /// The storage used by lockups can't be released by the depositor, so the account can only
+    /// be unregistered when none of its lockups hold storage.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        assert!(!force.unwrap_or(false), "Force unregister is not supported");
+        let account_id = env::predecessor_account_id();
+        if let Some(account_storage) = self.storage_accounts.get(&account_id) {
+            assert_eq!(
+                account_storage.used_bytes, 0,
+                "Can't unregister the account with storage used by lockups"
+            );
+            self.storage_accounts.remove(&account_id);
+            Promise::new(account_id).transfer(account_storage.deposit);
+            self.assert_near_locked_balance();
+            true
+        } else {
+            log!("The account {} is not registered", &account_id);
+            false
+        }
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: (Balance::from(self.account_storage_usage) * env::storage_byte_cost()).into(),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance_of(account_id.as_ref())
+    }
+}
+
+impl Contract {
+    pub(crate) fn measure_account_storage_usage(&mut self) {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = "a".repeat(64);
+        self.storage_accounts
+            .insert(&tmp_account_id, &AccountStorage::default());
+        self.account_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.storage_accounts.remove(&tmp_account_id);
+    }
+
+    fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
+        self.storage_accounts
+            .get(account_id)
+            .map(|account_storage| {
+                let used = Balance::from(self.account_storage_usage + account_storage.used_bytes)
+                    * env::storage_byte_cost();
+                StorageBalance {
+                    total: account_storage.deposit.into(),
+                    available: account_storage.deposit.saturating_sub(used).into(),
+                }
+            })
+    }
+
+    /// Charges the storage used since `initial_storage_usage` by the new lockup to the account.
+    pub(crate) fn internal_charge_lockup_storage(
+        &mut self,
+        account_id: &AccountId,
+        lockup_index: LockupIndex,
+        initial_storage_usage: StorageUsage,
+    ) {
+        let mut lockup_storage = LockupStorage {
+            account_id: account_id.clone(),
+            used_bytes: 0,
+        };
+        self.lockup_storage.insert(&lockup_index, &lockup_storage);
+        lockup_storage.used_bytes = env::storage_usage() - initial_storage_usage;
+        self.lockup_storage.insert(&lockup_index, &lockup_storage);
+
+        self.internal_charge_account_storage(account_id, lockup_storage.used_bytes);
+    }
+
+    /// Charges the given number of bytes to the account's prepaid storage.
+    pub(crate) fn internal_charge_account_storage(
+        &mut self,
+        account_id: &AccountId,
+        used_bytes: StorageUsage,
+    ) {
+        let mut account_storage = self
+            .storage_accounts
+            .get(account_id)
+            .expect("The depositor is not registered for storage");
+        account_storage.used_bytes += used_bytes;
+        let required = Balance::from(self.account_storage_usage + account_storage.used_bytes)
+            * env::storage_byte_cost();
+        assert!(
+            account_storage.deposit >= required,
+            "Not enough storage deposit. Required {}, available {}",
+            required,
+            account_storage.deposit
+        );
+        self.storage_accounts.insert(account_id, &account_storage);
+    }
+
+    /// Credits the given number of freed bytes back to the account's prepaid storage.
+    pub(crate) fn internal_release_account_storage(
+        &mut self,
+        account_id: &AccountId,
+        freed_bytes: StorageUsage,
+    ) {
+        let mut account_storage = self.storage_accounts.get(account_id).unwrap();
+        account_storage.used_bytes = account_storage.used_bytes.saturating_sub(freed_bytes);
+        self.storage_accounts.insert(account_id, &account_storage);
+    }
+
+    /// Credits the freed storage back to the account that paid for the lockup.
+    /// Once the whole charged storage is freed, the lockup storage record is removed.
+    pub(crate) fn internal_release_lockup_storage(
+        &mut self,
+        lockup_index: LockupIndex,
+        freed_bytes: StorageUsage,
+    ) {
+        let mut lockup_storage = match self.lockup_storage.get(&lockup_index) {
+            Some(lockup_storage) => lockup_storage,
+            None => return,
+        };
+        let released_bytes = std::cmp::min(freed_bytes, lockup_storage.used_bytes);
+        lockup_storage.used_bytes -= released_bytes;
+        let mut account_storage = self
+            .storage_accounts
+            .get(&lockup_storage.account_id)
+            .unwrap();
+        if lockup_storage.used_bytes == 0 {
+            // The record itself was charged as well.
+            self.lockup_storage.remove(&lockup_index);
+        } else {
+            self.lockup_storage.insert(&lockup_index, &lockup_storage);
+        }
+        account_storage.used_bytes -= released_bytes;
+        self.storage_accounts
+            .insert(&lockup_storage.account_id, &account_storage);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::*;
+use std::convert::TryInto;
+
+pub(crate) const GAS_FOR_STORAGE_BALANCE_OF: Gas = 5_000_000_000_000;
+const GAS_FOR_STORAGE_BALANCE_BOUNDS: Gas = 5_000_000_000_000;
+pub(crate) const GAS_FOR_STORAGE_DEPOSIT: Gas = 10_000_000_000_000;
+/// The gas kept to schedule the callback and to finish the execution.
+const GAS_RESERVED_FOR_CLAIM: Gas = 10_000_000_000_000;
+/// The gas of `after_storage_balance_check` itself, without the deposit and the claim.
+const GAS_FOR_AFTER_STORAGE_BALANCE_CHECK: Gas = 10_000_000_000_000;
+/// The gas of `after_token_registration_check` itself, without the claim.
+pub(crate) const GAS_FOR_AFTER_TOKEN_REGISTRATION_CHECK: Gas = 5_000_000_000_000;
+
+/// The claim, that runs once the token registration checks are resolved.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimRequest {
+    pub account_id: AccountId,
+    pub token_account_id: Option<TokenAccountId>,
+    pub keeper_fee: Option<KeeperFee>,
+}
+
+/// The NEP-145 methods of the token contracts.
+#[ext_contract(ext_token_storage)]
+pub trait TokenStorage {
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<ValidAccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance;
+
+    fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance>;
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds;
+}
+
+/// The part of the token's `storage_balance_bounds`, that is needed to register an account.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenStorageBounds {
+    pub min: WrappedBalance,
+}
+
+impl Contract {
+    /// Returns the fungible tokens, that the account would claim.
+    pub(crate) fn internal_claimed_token_ids(
+        &self,
+        account_id: &AccountId,
+        token_account_id: Option<&TokenAccountId>,
+    ) -> Vec<TokenAccountId> {
+        let mut token_ids: Vec<TokenAccountId> = self
+            .withdrawable
+            .get(account_id)
+            .unwrap_or_default()
+            .into_keys()
+            .collect();
+        for (lockup_index, mut lockup) in self.internal_get_account_lockups(account_id) {
+            if token_ids.contains(&lockup.token_account_id) {
+                continue;
+            }
+            let staked_balance = self.internal_staked_balance(lockup_index);
+            if lockup
+                .claim(lockup_index, staked_balance)
+                .unclaimed_balance
+                .0
+                > 0
+            {
+                token_ids.push(lockup.token_account_id);
+            }
+        }
+        token_ids.retain(|token_id| {
+            token_id != NEAR_TOKEN_ID
+                && (token_account_id.is_none() || token_account_id == Some(token_id))
+        });
+        token_ids
+    }
+
+    pub(crate) fn internal_is_token_registered(
+        &self,
+        account_id: &AccountId,
+        token_account_id: &TokenAccountId,
+    ) -> bool {
+        self.token_registrations
+            .contains(&(account_id.clone(), token_account_id.clone()))
+    }
+
+    pub(crate) fn internal_set_token_registered(
+        &mut self,
+        account_id: &AccountId,
+        token_account_id: &TokenAccountId,
+        is_registered: bool,
+    ) {
+        let key = (account_id.clone(), token_account_id.clone());
+        if is_registered {
+            self.token_registrations.insert(&key);
+        } else {
+            self.token_registrations.remove(&key);
+        }
+    }
+
+    /// Returns whether the account is known to be registered with the token, or with every
+    /// whitelisted token if none is given.
+    fn internal_is_registered_with_tokens(
+        &self,
+        account_id: &AccountId,
+        token_account_id: Option<&TokenAccountId>,
+    ) -> bool {
+        let is_registered = |token_id: &TokenAccountId| {
+            token_id == NEAR_TOKEN_ID || self.internal_is_token_registered(account_id, token_id)
+        };
+        match token_account_id {
+            Some(token_account_id) => is_registered(token_account_id),
+            None => self
+                .token_whitelist
+                .iter()
+                .all(|token_id| is_registered(&token_id)),
+        }
+    }
+
+    /// Claims once the account is registered with the claimed tokens. The attached deposit,
+    /// if any, is deposited to the account's storage on the claimed token.
+    /// Without the deposit, the tokens, that the account is known to be registered with,
+    /// aren't checked again. If it's known to be registered with all of them, the lockups
+    /// aren't scanned for the claimed tokens either.
+    pub(crate) fn internal_claim_with_registration_check(
+        &mut self,
+        account_id: AccountId,
+        token_account_id: Option<TokenAccountId>,
+        keeper_fee: Option<KeeperFee>,
+    ) -> PromiseOrValue<WrappedBalance> {
+        if env::attached_deposit() == 0
+            && self.internal_is_registered_with_tokens(&account_id, token_account_id.as_ref())
+        {
+            return self.internal_claim(account_id, token_account_id, keeper_fee);
+        }
+        let mut token_ids = self.internal_claimed_token_ids(&account_id, token_account_id.as_ref());
+        if env::attached_deposit() > 0 {
+            assert_eq!(
+                token_ids.len(),
+                1,
+                "The storage deposit can only be attached to the claim of a single token"
+            );
+        } else {
+            token_ids.retain(|token_id| !self.internal_is_token_registered(&account_id, token_id));
+        }
+        if token_ids.is_empty() {
+            self.internal_claim(account_id, token_account_id, keeper_fee)
+        } else {
+            self.internal_check_token_registration(
+                account_id,
+                token_account_id,
+                token_ids,
+                keeper_fee,
+            )
+        }
+    }
+
+    /// Checks that the account is registered with the tokens, before claiming them.
+    /// With the attached deposit, the account is registered with the single token if needed,
+    /// using only the minimum storage balance. The rest of the deposit is refunded.
+    fn internal_check_token_registration(
+        &mut self,
+        account_id: AccountId,
+        token_account_id: Option<TokenAccountId>,
+        token_ids: Vec<TokenAccountId>,
+        keeper_fee: Option<KeeperFee>,
+    ) -> PromiseOrValue<WrappedBalance> {
+        let storage_deposit = env::attached_deposit();
+        // Enough to claim at least one lockup of every token. The claim scans the lockups
+        // again, which costs about as much as this call has used so far.
+        let min_claim_gas = GAS_FOR_AFTER_TOKEN_REGISTRATION_CHECK
+            + env::used_gas()
+            + gas_for_claim(token_ids.len(), token_ids.len());
+        let claim_request = ClaimRequest {
+            account_id: account_id.clone(),
+            token_account_id,
+            keeper_fee,
+        };
+        if storage_deposit > 0 {
+            let token_id = token_ids.into_iter().next().unwrap();
+            let callback_gas = remaining_gas(
+                GAS_FOR_STORAGE_BALANCE_OF
+                    + GAS_FOR_STORAGE_BALANCE_BOUNDS
+                    + GAS_FOR_CALL_SCHEDULING * 2,
+            );
+            assert!(
+                callback_gas
+                    >= GAS_FOR_AFTER_STORAGE_BALANCE_CHECK
+                        + GAS_FOR_STORAGE_DEPOSIT
+                        + GAS_FOR_CALL_SCHEDULING
+                        + GAS_RESERVED_FOR_CLAIM
+                        + min_claim_gas,
+                "Not enough gas to claim"
+            );
+            return ext_token_storage::storage_balance_of(
+                account_id.try_into().unwrap(),
+                &token_id,
+                NO_DEPOSIT,
+                GAS_FOR_STORAGE_BALANCE_OF,
+            )
+            .and(ext_token_storage::storage_balance_bounds(
+                &token_id,
+                NO_DEPOSIT,
+                GAS_FOR_STORAGE_BALANCE_BOUNDS,
+            ))
+            .then(ext_self::after_storage_balance_check(
+                claim_request,
+                token_id,
+                storage_deposit.into(),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                callback_gas,
+            ))
+            .into();
+        }
+        let claim_gas = remaining_gas(
+            (GAS_FOR_STORAGE_BALANCE_OF + GAS_FOR_CALL_SCHEDULING) * token_ids.len() as Gas,
+        );
+        assert!(claim_gas >= min_claim_gas, "Not enough gas to claim");
+        internal_check_token_registration_then_claim(claim_request, token_ids, claim_gas).into()
+    }
+}
+
+/// Checks the account's registration with every token, and then claims with the given gas.
+pub(crate) fn internal_check_token_registration_then_claim(
+    claim_request: ClaimRequest,
+    token_ids: Vec<TokenAccountId>,
+    claim_gas: Gas,
+) -> Promise {
+    let mut checks: Option<Promise> = None;
+    for token_id in &token_ids {
+        let check = ext_token_storage::storage_balance_of(
+            claim_request.account_id.clone().try_into().unwrap(),
+            token_id,
+            NO_DEPOSIT,
+            GAS_FOR_STORAGE_BALANCE_OF,
+        );
+        checks = Some(match checks {
+            Some(checks) => checks.and(check),
+            None => check,
+        });
+    }
+    checks
+        .unwrap()
+        .then(ext_self::after_token_registration_check(
+            claim_request,
+            token_ids,
+            0.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            claim_gas,
+        ))
+}
+
+/// Returns the prepaid gas, that is left after the given calls and the gas kept to finish.
+pub(crate) fn remaining_gas(scheduled_gas: Gas) -> Gas {
+    env::prepaid_gas().saturating_sub(env::used_gas() + scheduled_gas + GAS_RESERVED_FOR_CLAIM)
+}
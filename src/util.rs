@@ -8,6 +8,43 @@ pub(crate) fn current_timestamp_sec() -> TimestampSec {
     nano_to_sec(env::block_timestamp())
 }
 
+/// Returns the gas of the claim's `after_ft_transfer` for the given number of tokens and
+/// claimed lockups.
+pub(crate) const fn gas_for_after_ft_transfer(num_tokens: usize, num_lockup_claims: usize) -> Gas {
+    GAS_FOR_AFTER_CLAIM_TRANSFER
+        + GAS_FOR_AFTER_CLAIM_TRANSFER_PER_TOKEN * num_tokens as Gas
+        + GAS_FOR_AFTER_CLAIM_TRANSFER_PER_LOCKUP * num_lockup_claims as Gas
+}
+
+/// Asserts that the token ID is either `NEAR_TOKEN_ID` or a valid account ID.
+pub(crate) fn assert_valid_token_id(token_account_id: &str) {
+    assert!(
+        token_account_id == NEAR_TOKEN_ID || env::is_valid_account_id(token_account_id.as_bytes()),
+        "Invalid token ID"
+    );
+}
+
+fn page_range(len: u32, from_index: Option<u32>, limit: Option<u32>) -> std::ops::Range<u32> {
+    let from_index = std::cmp::min(from_index.unwrap_or(0), len);
+    let limit = limit.unwrap_or(len);
+    from_index..std::cmp::min(len, from_index.saturating_add(limit))
+}
+
+pub(crate) fn paginate<T>(values: &[T], from_index: Option<u32>, limit: Option<u32>) -> &[T] {
+    let range = page_range(values.len() as _, from_index, limit);
+    &values[range.start as usize..range.end as usize]
+}
+
+pub(crate) fn paginate_vector<T: BorshDeserialize + BorshSerialize>(
+    vector: &Vector<T>,
+    from_index: Option<u32>,
+    limit: Option<u32>,
+) -> Vec<T> {
+    page_range(vector.len() as _, from_index, limit)
+        .map(|index| vector.get(index as _).unwrap())
+        .collect()
+}
+
 pub mod u128_dec_format {
     use near_sdk::serde::de;
     use near_sdk::serde::{Deserialize, Deserializer, Serializer};
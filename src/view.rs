@@ -5,7 +5,10 @@ use crate::*;
 #[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Deserialize))]
 pub struct LockupView {
     pub account_id: ValidAccountId,
+    pub token_account_id: TokenAccountId,
     pub schedule: Schedule,
+    /// The ID of the registered schedule shape, if the schedule was resolved from it.
+    pub schedule_id: Option<Base58CryptoHash>,
 
     #[serde(default)]
     #[serde(with = "u128_dec_format")]
@@ -19,6 +22,22 @@ pub struct LockupView {
     pub unclaimed_balance: Balance,
     /// The current timestamp
     pub timestamp: TimestampSec,
+    /// Whether the lockup is fully claimed and archived. The schedule of an archived lockup
+    /// only contains the final checkpoint.
+    #[serde(default)]
+    pub archived: bool,
+
+    pub creator_id: Option<AccountId>,
+    pub created_at: Option<TimestampSec>,
+    pub memo: Option<String>,
+    pub category: Option<String>,
+    /// The timestamp until which the creator can cancel the lockup, if it's cancellable.
+    pub cancellable_until: Option<TimestampSec>,
+    /// The timestamp after which the treasury can claw back the unclaimed balance.
+    pub claim_deadline: Option<TimestampSec>,
+    /// Whether the claim deadline is within `CLAIM_DEADLINE_WARNING_PERIOD` or has passed.
+    #[serde(default)]
+    pub expires_soon: bool,
 }
 
 impl From<Lockup> for LockupView {
@@ -27,38 +46,181 @@ impl From<Lockup> for LockupView {
         let timestamp = current_timestamp_sec();
         let unclaimed_balance =
             lockup.schedule.unlocked_balance(timestamp) - lockup.claimed_balance;
+        let cancellable_until = lockup.cancellable_until();
+        let claim_deadline = lockup.claim_deadline;
         let Lockup {
             account_id,
+            token_account_id,
             schedule,
+            schedule_id,
             claimed_balance,
             termination_config,
+            creator_id,
+            created_at,
+            memo,
+            category,
+            ..
         } = lockup;
         Self {
             account_id,
+            token_account_id,
             schedule,
+            schedule_id,
             claimed_balance,
             termination_config,
             total_balance,
             unclaimed_balance,
             timestamp,
+            archived: false,
+            creator_id,
+            created_at,
+            memo,
+            category,
+            cancellable_until,
+            claim_deadline,
+            expires_soon: false,
+        }
+    }
+}
+
+impl From<ArchivedLockup> for LockupView {
+    fn from(lockup: ArchivedLockup) -> Self {
+        Self {
+            account_id: lockup.account_id,
+            token_account_id: lockup.token_account_id,
+            schedule: Schedule(vec![Checkpoint {
+                timestamp: lockup.finished_at,
+                balance: lockup.total_balance,
+            }]),
+            schedule_id: None,
+            claimed_balance: lockup.total_balance,
+            termination_config: None,
+            total_balance: lockup.total_balance,
+            unclaimed_balance: 0,
+            timestamp: current_timestamp_sec(),
+            archived: true,
+            creator_id: lockup.creator_id,
+            created_at: lockup.created_at,
+            memo: None,
+            category: lockup.category,
+            cancellable_until: None,
+            claim_deadline: None,
+            expires_soon: false,
         }
     }
 }
 
+/// The maximum number of lockups scanned by a single `get_lockups_paged` call.
+pub const MAX_LOCKUPS_SCANNED_PER_PAGE: u32 = 500;
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Serialize))]
+pub enum LockupStatus {
+    /// The lockup still has balance to claim.
+    Active,
+    /// The lockup is fully claimed.
+    Finished,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Serialize))]
+pub struct LockupFilter {
+    pub account_id: Option<ValidAccountId>,
+    pub token_account_id: Option<TokenAccountId>,
+    pub terminator_id: Option<ValidAccountId>,
+    pub status: Option<LockupStatus>,
+    /// Whether the lockup still has a termination config.
+    pub terminable: Option<bool>,
+    pub creator_id: Option<ValidAccountId>,
+    pub category: Option<String>,
+}
+
+impl LockupFilter {
+    pub fn matches(&self, lockup: &LockupView) -> bool {
+        if let Some(account_id) = &self.account_id {
+            if account_id != &lockup.account_id {
+                return false;
+            }
+        }
+        if let Some(token_account_id) = &self.token_account_id {
+            if token_account_id != &lockup.token_account_id {
+                return false;
+            }
+        }
+        if let Some(terminator_id) = &self.terminator_id {
+            match &lockup.termination_config {
+                Some(config) if &config.terminator_id == terminator_id => {}
+                _ => return false,
+            }
+        }
+        if let Some(status) = self.status {
+            let is_finished = lockup.claimed_balance == lockup.total_balance;
+            match status {
+                LockupStatus::Active if is_finished => return false,
+                LockupStatus::Finished if !is_finished => return false,
+                _ => {}
+            }
+        }
+        if let Some(terminable) = self.terminable {
+            if terminable != lockup.termination_config.is_some() {
+                return false;
+            }
+        }
+        if let Some(creator_id) = &self.creator_id {
+            if Some(creator_id.as_ref()) != lockup.creator_id.as_ref() {
+                return false;
+            }
+        }
+        if let Some(category) = &self.category {
+            if Some(category) != lockup.category.as_ref() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Deserialize))]
+pub struct LockupsPage {
+    pub lockups: Vec<(LockupIndex, LockupView)>,
+    /// The index to continue from, or `None` if all lockups were scanned.
+    pub next_index: Option<LockupIndex>,
+}
+
 #[near_bindgen]
 impl Contract {
+    /// Returns the account's active lockups. The lockups are in the order they were added,
+    /// except that a removed lockup is replaced by the last one.
     pub fn get_account_lockups(
         &self,
         account_id: ValidAccountId,
+        from_index: Option<u32>,
+        limit: Option<u32>,
     ) -> Vec<(LockupIndex, LockupView)> {
-        self.internal_get_account_lockups(account_id.as_ref())
+        self.internal_get_account_lockup_indices_page(account_id.as_ref(), from_index, limit)
             .into_iter()
-            .map(|(lockup_index, lockup)| (lockup_index, lockup.into()))
+            .map(|lockup_index| {
+                let lockup = self.internal_unwrap_lockup(lockup_index);
+                (lockup_index, self.internal_lockup_view(lockup))
+            })
             .collect()
     }
 
+    pub fn get_num_account_lockups(&self, account_id: ValidAccountId) -> u32 {
+        self.internal_get_num_account_lockups(account_id.as_ref())
+    }
+
     pub fn get_lockup(&self, index: LockupIndex) -> Option<LockupView> {
-        self.lockups.get(index as _).map(|lockup| lockup.into())
+        self.internal_get_stored_lockup(index)
+            .map(|lockup| match lockup {
+                StoredLockup::Archived(lockup) => lockup.into(),
+                _ => self.internal_lockup_view(self.internal_unwrap_lockup(index)),
+            })
     }
 
     pub fn get_lockups(&self, indices: Vec<LockupIndex>) -> Vec<(LockupIndex, LockupView)> {
@@ -72,20 +234,73 @@ impl Contract {
         self.lockups.len() as _
     }
 
+    /// Returns up to `limit` lockups matching the filter, starting from `from_index`.
+    /// At most `MAX_LOCKUPS_SCANNED_PER_PAGE` lockups are scanned per call, so the page can
+    /// contain fewer lockups even if there are more matching ones. Use `next_index` to
+    /// continue until it's `None`.
     pub fn get_lockups_paged(
         &self,
         from_index: Option<LockupIndex>,
         limit: Option<LockupIndex>,
-    ) -> Vec<(LockupIndex, LockupView)> {
-        let from_index = from_index.unwrap_or(0);
-        let limit = limit.unwrap_or(self.get_num_lockups());
-        (from_index..std::cmp::min(self.get_num_lockups(), limit))
-            .filter_map(|index| self.get_lockup(index).map(|lockup| (index, lockup)))
-            .collect()
+        filter: Option<LockupFilter>,
+    ) -> LockupsPage {
+        assert_ne!(limit, Some(0), "The limit must be positive");
+        let num_lockups = self.get_num_lockups();
+        let limit = limit.unwrap_or(num_lockups) as usize;
+        let filter = filter.unwrap_or_default();
+        let mut index = from_index.unwrap_or(0);
+        let scan_end = std::cmp::min(
+            num_lockups,
+            index.saturating_add(MAX_LOCKUPS_SCANNED_PER_PAGE),
+        );
+        let mut lockups = vec![];
+        while index < scan_end && lockups.len() < limit {
+            let lockup = self.get_lockup(index).unwrap();
+            if filter.matches(&lockup) {
+                lockups.push((index, lockup));
+            }
+            index += 1;
+        }
+        LockupsPage {
+            lockups,
+            next_index: if index < num_lockups {
+                Some(index)
+            } else {
+                None
+            },
+        }
+    }
+
+    pub fn get_deposit_whitelist(
+        &self,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<AccountId> {
+        paginate_vector(self.deposit_whitelist.as_vector(), from_index, limit)
+    }
+
+    pub fn get_num_deposit_whitelist(&self) -> u32 {
+        self.deposit_whitelist.len() as _
+    }
+
+    pub fn is_whitelisted(&self, account_id: ValidAccountId) -> bool {
+        self.deposit_whitelist.contains(account_id.as_ref())
+    }
+
+    pub fn get_token_whitelist(
+        &self,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<TokenAccountId> {
+        paginate_vector(self.token_whitelist.as_vector(), from_index, limit)
     }
 
-    pub fn get_deposit_whitelist(&self) -> Vec<AccountId> {
-        self.deposit_whitelist.to_vec()
+    pub fn get_num_token_whitelist(&self) -> u32 {
+        self.token_whitelist.len() as _
+    }
+
+    pub fn is_token_whitelisted(&self, token_account_id: ValidAccountId) -> bool {
+        self.token_whitelist.contains(token_account_id.as_ref())
     }
 
     pub fn hash_schedule(schedule: Schedule) -> Base58CryptoHash {
@@ -104,7 +319,37 @@ impl Contract {
         }
     }
 
-    pub fn get_blacklist(&self) -> Vec<AccountId> {
-        self.blacklist.to_vec()
+    pub fn get_blacklist(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<AccountId> {
+        paginate_vector(self.blacklist.keys_as_vector(), from_index, limit)
+    }
+
+    pub fn get_num_blacklist(&self) -> u32 {
+        self.blacklist.len() as _
+    }
+
+    /// Returns true if the account is blacklisted and the entry hasn't expired.
+    pub fn is_blacklisted(&self, account_id: ValidAccountId) -> bool {
+        self.blacklist
+            .get(account_id.as_ref())
+            .map(|entry| entry.is_active(current_timestamp_sec()))
+            .unwrap_or(false)
+    }
+}
+
+impl Contract {
+    /// Returns the lockup view with the schedule delayed by the account's frozen duration so far,
+    /// and with the effective claim deadline.
+    pub(crate) fn internal_lockup_view(&self, mut lockup: Lockup) -> LockupView {
+        self.internal_apply_active_freeze(&mut lockup);
+        let claim_deadline = self.internal_claim_deadline(&lockup);
+        let mut view: LockupView = lockup.into();
+        view.claim_deadline = claim_deadline;
+        view.expires_soon = view.claimed_balance < view.total_balance
+            && claim_deadline
+                .map(|claim_deadline| {
+                    view.timestamp.saturating_add(CLAIM_DEADLINE_WARNING_PERIOD) >= claim_deadline
+                })
+                .unwrap_or(false);
+        view
     }
 }
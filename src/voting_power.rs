@@ -0,0 +1,147 @@
+use crate::view::MAX_LOCKUPS_SCANNED_PER_PAGE;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub enum VotingPowerWeighting {
+    /// The locked balance counts in full.
+    Flat,
+    /// The locked balance is weighted by the remaining lock duration of the lockup, from the
+    /// full weight at `max_duration` or more down to zero once the lockup is fully unlocked.
+    Decaying { max_duration: TimestampSec },
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct VotingPowerConfig {
+    /// The token that gives voting power. Lockups of other tokens are ignored.
+    pub token_account_id: TokenAccountId,
+    pub weighting: VotingPowerWeighting,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Deserialize))]
+pub struct VotingPowerPage {
+    /// The voting power of the scanned lockups.
+    pub voting_power: WrappedBalance,
+    /// The index to continue from, or `None` if all lockups were scanned.
+    pub next_index: Option<LockupIndex>,
+}
+
+impl VotingPowerConfig {
+    /// Returns the voting power of the lockup schedule at the given timestamp.
+    pub fn voting_power(&self, schedule: &Schedule, timestamp: TimestampSec) -> Balance {
+        let locked_balance = schedule.total_balance() - schedule.unlocked_balance(timestamp);
+        match self.weighting {
+            VotingPowerWeighting::Flat => locked_balance,
+            VotingPowerWeighting::Decaying { max_duration } => {
+                let finished_at = schedule.0.last().unwrap().timestamp;
+                let remaining_duration =
+                    std::cmp::min(finished_at.saturating_sub(timestamp), max_duration);
+                (U256::from(locked_balance) * U256::from(remaining_duration)
+                    / U256::from(max_duration))
+                .as_u128()
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    #[payable]
+    pub fn set_voting_power_config(&mut self, config: VotingPowerConfig) {
+        assert_one_yocto();
+        self.assert_owner();
+        if let VotingPowerWeighting::Decaying { max_duration } = config.weighting {
+            assert!(max_duration > 0, "The max duration should be positive");
+        }
+        self.voting_power_config = Some(config);
+    }
+
+    pub fn get_voting_power_config(&self) -> Option<VotingPowerConfig> {
+        self.voting_power_config.clone()
+    }
+
+    /// Returns the voting power of the account's locked balance at the given timestamp, or now.
+    /// The power is derived from the current schedules, so past timestamps don't account for
+    /// the lockups that were claimed or terminated since.
+    pub fn get_voting_power(
+        &self,
+        account_id: ValidAccountId,
+        at_timestamp: Option<TimestampSec>,
+    ) -> WrappedBalance {
+        let config = self.internal_voting_power_config();
+        let timestamp = at_timestamp.unwrap_or_else(current_timestamp_sec);
+        self.internal_get_account_lockups(account_id.as_ref())
+            .into_iter()
+            .map(|(_, lockup)| self.internal_lockup_voting_power(config, lockup, timestamp))
+            .sum::<Balance>()
+            .into()
+    }
+
+    /// Returns the total voting power of the lockups from `from_index` at the given timestamp,
+    /// or now. Scans up to `limit` lockups, but at most `MAX_LOCKUPS_SCANNED_PER_PAGE`.
+    /// The total voting power is the sum of the pages until `next_index` is `None`.
+    pub fn get_total_voting_power(
+        &self,
+        at_timestamp: Option<TimestampSec>,
+        from_index: Option<LockupIndex>,
+        limit: Option<LockupIndex>,
+    ) -> VotingPowerPage {
+        assert_ne!(limit, Some(0), "The limit must be positive");
+        let config = self.internal_voting_power_config();
+        let timestamp = at_timestamp.unwrap_or_else(current_timestamp_sec);
+        let num_lockups = self.lockups.len() as LockupIndex;
+        let from_index = from_index.unwrap_or(0);
+        let limit = std::cmp::min(
+            limit.unwrap_or(MAX_LOCKUPS_SCANNED_PER_PAGE),
+            MAX_LOCKUPS_SCANNED_PER_PAGE,
+        );
+        let scan_end = std::cmp::min(num_lockups, from_index.saturating_add(limit));
+        let voting_power = (from_index..scan_end)
+            .filter(|&index| {
+                !matches!(
+                    self.internal_get_stored_lockup(index),
+                    Some(StoredLockup::Archived(_))
+                )
+            })
+            .map(|index| {
+                let lockup = self.internal_unwrap_lockup(index);
+                self.internal_lockup_voting_power(config, lockup, timestamp)
+            })
+            .sum::<Balance>();
+        VotingPowerPage {
+            voting_power: voting_power.into(),
+            next_index: if scan_end < num_lockups {
+                Some(scan_end)
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_voting_power_config(&self) -> &VotingPowerConfig {
+        self.voting_power_config
+            .as_ref()
+            .expect("Voting power is not configured")
+    }
+
+    fn internal_lockup_voting_power(
+        &self,
+        config: &VotingPowerConfig,
+        mut lockup: Lockup,
+        timestamp: TimestampSec,
+    ) -> Balance {
+        if lockup.token_account_id != config.token_account_id {
+            return 0;
+        }
+        self.internal_apply_active_freeze(&mut lockup);
+        config.voting_power(&lockup.schedule, timestamp)
+    }
+}
@@ -0,0 +1,248 @@
+use crate::*;
+use ed25519_dalek::{PublicKey, Signature};
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8};
+use std::convert::TryFrom;
+
+/// The prefix of the signed voucher messages, that separates them from the other messages
+/// signed with the same key.
+pub const VOUCHER_MESSAGE_PREFIX: &[u8] = b"ft-lockup:voucher:";
+
+/// A lockup grant signed off-chain by a depositor with one of its registered ed25519 keys.
+/// The lockup is funded from the depositor's voucher pool of the token once the beneficiary
+/// redeems the voucher.
+#[derive(BorshSerialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, Clone, Serialize))]
+pub struct Voucher {
+    pub depositor_id: ValidAccountId,
+    /// The beneficiary of the lockup, that redeems the voucher.
+    pub account_id: ValidAccountId,
+    pub token_account_id: ValidAccountId,
+    pub schedule: Schedule,
+    /// The depositor's nonce. Every nonce can only be redeemed once.
+    pub nonce: u64,
+    /// The timestamp after which the voucher can't be redeemed.
+    pub expires_at: TimestampSec,
+}
+
+impl Voucher {
+    /// Returns the signed message: the prefix followed by the borsh serialization of the
+    /// lockup contract ID and the voucher, so the voucher can't be redeemed on another contract.
+    pub fn message(&self, contract_id: &str) -> Vec<u8> {
+        [
+            VOUCHER_MESSAGE_PREFIX,
+            &contract_id.try_to_vec().unwrap(),
+            &self.try_to_vec().unwrap(),
+        ]
+        .concat()
+    }
+}
+
+/// The deposit message that funds the depositor's voucher pool of the transferred token.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+pub struct VoucherPoolDeposit {}
+
+/// Returns the ed25519 key bytes. Panics on the other curves.
+fn ed25519_key_bytes(public_key: Base58PublicKey) -> Vec<u8> {
+    let bytes: Vec<u8> = public_key.into();
+    assert_eq!(bytes[0], 0, "Only ed25519 keys are supported");
+    bytes[1..].to_vec()
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Registers the ed25519 key, that signs the vouchers of the depositor. The storage is
+    /// charged to the depositor's prepaid storage balance.
+    #[payable]
+    pub fn add_voucher_public_key(&mut self, public_key: Base58PublicKey) {
+        assert_one_yocto();
+        let depositor_id = env::predecessor_account_id();
+        self.assert_deposit_whitelist(&depositor_id);
+        let key = ed25519_key_bytes(public_key);
+        PublicKey::from_bytes(&key).expect("Invalid public key");
+        let initial_storage_usage = env::storage_usage();
+        assert!(
+            self.voucher_keys.insert(&(depositor_id.clone(), key)),
+            "The key is already registered"
+        );
+        self.internal_charge_account_storage(
+            &depositor_id,
+            env::storage_usage() - initial_storage_usage,
+        );
+    }
+
+    /// Revokes the key. The vouchers signed with it can't be redeemed anymore.
+    #[payable]
+    pub fn remove_voucher_public_key(&mut self, public_key: Base58PublicKey) {
+        assert_one_yocto();
+        let depositor_id = env::predecessor_account_id();
+        let initial_storage_usage = env::storage_usage();
+        assert!(
+            self.voucher_keys
+                .remove(&(depositor_id.clone(), ed25519_key_bytes(public_key))),
+            "The key is not registered"
+        );
+        self.internal_release_account_storage(
+            &depositor_id,
+            initial_storage_usage - env::storage_usage(),
+        );
+    }
+
+    /// Creates the beneficiary's lockup from the voucher signed by the depositor with the given
+    /// key, and debits the depositor's voucher pool. The storage of the lockup and the redeemed
+    /// nonce is charged to the depositor's prepaid storage balance.
+    pub fn redeem_voucher(
+        &mut self,
+        voucher: Voucher,
+        public_key: Base58PublicKey,
+        signature: Base64VecU8,
+    ) -> LockupIndex {
+        self.assert_not_paused(PauseKind::Deposit);
+        let account_id = env::predecessor_account_id();
+        self.assert_not_blacklisted(&account_id);
+        assert_eq!(
+            voucher.account_id.as_ref(),
+            &account_id,
+            "The voucher is for another account"
+        );
+        assert!(
+            current_timestamp_sec() < voucher.expires_at,
+            "The voucher has expired"
+        );
+        let depositor_id: AccountId = voucher.depositor_id.clone().into();
+        self.assert_deposit_whitelist(&depositor_id);
+        let key = ed25519_key_bytes(public_key);
+        assert!(
+            self.voucher_keys
+                .contains(&(depositor_id.clone(), key.clone())),
+            "The key is not registered by the depositor"
+        );
+        let signature: Vec<u8> = signature.into();
+        let signature = Signature::try_from(signature.as_slice()).expect("Invalid signature");
+        PublicKey::from_bytes(&key)
+            .unwrap()
+            .verify_strict(&voucher.message(&env::current_account_id()), &signature)
+            .expect("Invalid signature");
+
+        let initial_storage_usage = env::storage_usage();
+        assert!(
+            self.voucher_nonces
+                .insert(&(depositor_id.clone(), voucher.nonce)),
+            "The voucher is already redeemed"
+        );
+        self.internal_charge_account_storage(
+            &depositor_id,
+            env::storage_usage() - initial_storage_usage,
+        );
+        let token_account_id: TokenAccountId = voucher.token_account_id.into();
+        let pool_key = (depositor_id.clone(), token_account_id.clone());
+        let amount = voucher.schedule.total_balance();
+        let pool_balance = self.voucher_pools.get(&pool_key).unwrap_or(0);
+        assert!(
+            amount <= pool_balance,
+            "Not enough balance in the voucher pool"
+        );
+        self.voucher_pools
+            .insert(&pool_key, &(pool_balance - amount));
+        let lockup = Lockup {
+            account_id: voucher.account_id,
+            token_account_id,
+            schedule: voucher.schedule,
+            schedule_id: None,
+            claimed_balance: 0,
+            termination_config: None,
+            creator_id: None,
+            created_at: None,
+            memo: Some(format!("Voucher #{}", voucher.nonce)),
+            category: None,
+            // The voucher lockups can't be cancelled.
+            cancellation_deadline: Some(0),
+            claim_deadline: None,
+            num_applied_freezes: 0,
+        };
+        self.internal_create_lockup(&depositor_id, lockup, amount)
+    }
+
+    /// Refunds the balance of the caller's voucher pool of the token. Withdraws the whole
+    /// balance, unless `amount` is given. The unredeemed vouchers can't be redeemed without
+    /// the balance.
+    pub fn withdraw_voucher_pool(
+        &mut self,
+        token_account_id: ValidAccountId,
+        amount: Option<WrappedBalance>,
+    ) -> PromiseOrValue<WrappedBalance> {
+        self.assert_not_paused(PauseKind::Terminate);
+        let depositor_id = env::predecessor_account_id();
+        let token_account_id: TokenAccountId = token_account_id.into();
+        let pool_key = (depositor_id.clone(), token_account_id.clone());
+        let balance = self.voucher_pools.get(&pool_key).unwrap_or(0);
+        let amount = amount.map(|amount| amount.0).unwrap_or(balance);
+        assert!(amount > 0, "Nothing to withdraw");
+        assert!(amount <= balance, "Not enough balance in the voucher pool");
+        self.voucher_pools.insert(&pool_key, &(balance - amount));
+        self.internal_transfer(
+            &token_account_id,
+            depositor_id.clone(),
+            amount,
+            "Refund of the voucher pool".to_string(),
+        )
+        .then(ext_self::after_lockup_termination(
+            depositor_id,
+            token_account_id,
+            amount.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_AFTER_FT_TRANSFER,
+        ))
+        .into()
+    }
+
+    pub fn get_voucher_pool_balance(
+        &self,
+        depositor_id: ValidAccountId,
+        token_account_id: ValidAccountId,
+    ) -> WrappedBalance {
+        self.voucher_pools
+            .get(&(depositor_id.into(), token_account_id.into()))
+            .unwrap_or(0)
+            .into()
+    }
+
+    pub fn is_voucher_redeemed(&self, depositor_id: ValidAccountId, nonce: u64) -> bool {
+        self.voucher_nonces.contains(&(depositor_id.into(), nonce))
+    }
+
+    /// Returns the message to sign for the voucher.
+    pub fn get_voucher_message(&self, voucher: Voucher) -> Base64VecU8 {
+        voucher.message(&env::current_account_id()).into()
+    }
+}
+
+impl Contract {
+    /// Credits the depositor's voucher pool of the token. The storage of a new pool is charged
+    /// to the depositor's prepaid storage balance.
+    pub(crate) fn internal_fund_voucher_pool(
+        &mut self,
+        depositor_id: &AccountId,
+        token_account_id: TokenAccountId,
+        amount: Balance,
+    ) {
+        assert!(amount > 0, "The voucher pool deposit should be positive");
+        let pool_key = (depositor_id.clone(), token_account_id);
+        let initial_storage_usage = env::storage_usage();
+        let balance = self.voucher_pools.get(&pool_key).unwrap_or(0) + amount;
+        self.voucher_pools.insert(&pool_key, &balance);
+        self.internal_charge_account_storage(
+            depositor_id,
+            env::storage_usage() - initial_storage_usage,
+        );
+        log!(
+            "Voucher pool of {} {} is {}",
+            depositor_id,
+            pool_key.1,
+            balance
+        );
+    }
+}
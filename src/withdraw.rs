@@ -0,0 +1,143 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Transfers the withdrawable balance of the given token, that was credited after failed
+    /// claim transfers. Withdraws the whole balance, unless `amount` is given.
+    pub fn withdraw(
+        &mut self,
+        token_account_id: TokenAccountId,
+        amount: Option<WrappedBalance>,
+    ) -> PromiseOrValue<WrappedBalance> {
+        self.assert_not_paused(PauseKind::Claim);
+        let account_id = env::predecessor_account_id();
+        self.assert_not_blacklisted(&account_id);
+        assert_valid_token_id(&token_account_id);
+        self.internal_lock_claims(&account_id);
+        let mut withdrawable = self.withdrawable.get(&account_id).unwrap_or_default();
+        let balance = withdrawable
+            .get(&token_account_id)
+            .cloned()
+            .unwrap_or_default();
+        let amount = amount.map(|amount| amount.0).unwrap_or(balance);
+        assert!(amount > 0, "Nothing to withdraw");
+        assert!(amount <= balance, "Not enough withdrawable balance");
+        if amount == balance {
+            withdrawable.remove(&token_account_id);
+        } else {
+            withdrawable.insert(token_account_id.clone(), balance - amount);
+        }
+        self.internal_save_withdrawable(&account_id, withdrawable);
+        log!("Withdrawing {} {}", amount, token_account_id);
+
+        self.internal_transfer(
+            &token_account_id,
+            account_id.clone(),
+            amount,
+            format!(
+                "Withdrawing {} balance from {}",
+                amount,
+                env::current_account_id()
+            ),
+        )
+        .then(ext_self::after_ft_transfer(
+            account_id,
+            vec![TokenClaims {
+                token_account_id,
+                lockup_claims: vec![],
+                pending_balance: amount,
+                keeper_fee: 0,
+            }],
+            None,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            gas_for_after_ft_transfer(1, 0),
+        ))
+        .into()
+    }
+
+    /// If enabled, the failed claim transfers roll back the claimed balances of the lockups,
+    /// instead of crediting the withdrawable balance.
+    #[payable]
+    pub fn set_rollback_failed_claims(&mut self, enabled: bool) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.rollback_failed_claims = enabled;
+    }
+
+    pub fn get_rollback_failed_claims(&self) -> bool {
+        self.rollback_failed_claims
+    }
+
+    /// Returns the withdrawable balances of the account by token.
+    pub fn get_withdrawable(
+        &self,
+        account_id: ValidAccountId,
+    ) -> HashMap<TokenAccountId, WrappedBalance> {
+        self.withdrawable
+            .get(account_id.as_ref())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(token_account_id, balance)| (token_account_id, balance.into()))
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Credits the withdrawable balance after a failed transfer. The storage is paid by the
+    /// contract.
+    pub(crate) fn internal_credit_withdrawable(
+        &mut self,
+        account_id: &AccountId,
+        token_account_id: &TokenAccountId,
+        amount: Balance,
+    ) {
+        if amount == 0 {
+            return;
+        }
+        let mut withdrawable = self.withdrawable.get(account_id).unwrap_or_default();
+        *withdrawable.entry(token_account_id.clone()).or_default() += amount;
+        self.withdrawable.insert(account_id, &withdrawable);
+        log!(
+            "Added {} {} to the withdrawable balance of {}",
+            amount,
+            token_account_id,
+            account_id
+        );
+    }
+
+    /// Takes the withdrawable balances of the account, that match the token filter.
+    pub(crate) fn internal_take_withdrawable(
+        &mut self,
+        account_id: &AccountId,
+        token_account_id: Option<&TokenAccountId>,
+    ) -> Vec<(TokenAccountId, Balance)> {
+        let mut withdrawable = match self.withdrawable.get(account_id) {
+            Some(withdrawable) => withdrawable,
+            None => return vec![],
+        };
+        let mut res = vec![];
+        withdrawable.retain(|token, balance| {
+            if token_account_id.is_none() || token_account_id == Some(token) {
+                res.push((token.clone(), *balance));
+                false
+            } else {
+                true
+            }
+        });
+        self.internal_save_withdrawable(account_id, withdrawable);
+        res
+    }
+
+    fn internal_save_withdrawable(
+        &mut self,
+        account_id: &AccountId,
+        withdrawable: HashMap<TokenAccountId, Balance>,
+    ) {
+        if withdrawable.is_empty() {
+            self.withdrawable.remove(account_id);
+        } else {
+            self.withdrawable.insert(account_id, &withdrawable);
+        }
+    }
+}
@@ -1,9 +1,13 @@
 mod setup;
 
 use crate::setup::*;
+use ed25519_dalek::Signer;
 use ft_lockup::lockup::Lockup;
 use ft_lockup::schedule::{Checkpoint, Schedule};
-use near_sdk::json_types::WrappedBalance;
+use near_sdk::borsh::BorshSerialize;
+use near_sdk::json_types::{Base58CryptoHash, WrappedBalance};
+use near_sdk::serde_json;
+use near_sdk_sim::to_yocto;
 
 const ONE_DAY_SEC: TimestampSec = 24 * 60 * 60;
 const ONE_YEAR_SEC: TimestampSec = 365 * ONE_DAY_SEC;
@@ -24,9 +28,9 @@ fn test_lockup_claim_logic() {
     e.set_time_sec(GENESIS_TIMESTAMP_SEC);
     let lockups = e.get_account_lockups(&users.alice);
     assert!(lockups.is_empty());
-    let lockup = Lockup {
-        account_id: users.alice.valid_account_id(),
-        schedule: Schedule(vec![
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
             Checkpoint {
                 timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC - 1,
                 balance: 0,
@@ -36,9 +40,7 @@ fn test_lockup_claim_logic() {
                 balance: amount,
             },
         ]),
-        claimed_balance: 0,
-        termination_config: None,
-    };
+    );
     let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
     assert_eq!(balance.0, amount);
     let lockups = e.get_account_lockups(&users.alice);
@@ -65,7 +67,7 @@ fn test_lockup_claim_logic() {
     assert_eq!(lockups[0].1.claimed_balance, 0);
     assert_eq!(lockups[0].1.unclaimed_balance, amount);
 
-    // Attempt to claim. No storage deposit for Alice.
+    // Attempt to claim. No storage deposit for Alice, so nothing is claimed.
     let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
     assert_eq!(res.0, 0);
     let lockups = e.get_account_lockups(&users.alice);
@@ -101,9 +103,9 @@ fn test_lockup_linear() {
     e.set_time_sec(GENESIS_TIMESTAMP_SEC);
     let lockups = e.get_account_lockups(&users.alice);
     assert!(lockups.is_empty());
-    let lockup = Lockup {
-        account_id: users.alice.valid_account_id(),
-        schedule: Schedule(vec![
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
             Checkpoint {
                 timestamp: GENESIS_TIMESTAMP_SEC,
                 balance: 0,
@@ -113,9 +115,7 @@ fn test_lockup_linear() {
                 balance: amount,
             },
         ]),
-        claimed_balance: 0,
-        termination_config: None,
-    };
+    );
     let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
     assert_eq!(balance.0, amount);
     let lockups = e.get_account_lockups(&users.alice);
@@ -151,19 +151,27 @@ fn test_lockup_linear() {
     assert_eq!(lockups[0].1.claimed_balance, amount / 3);
     assert_eq!(lockups[0].1.unclaimed_balance, amount / 6);
 
-    // Remove storage from token to verify claim refund.
+    // Remove storage from token to verify the failed transfer and the registration check.
     // Note, this burns `amount / 3` tokens.
     storage_force_unregister(&users.alice, TOKEN_ID);
     let balance = e.ft_balance_of(&users.alice);
     assert_eq!(balance, 0);
 
-    // Trying to claim, should fail and refund the amount back to the lockup
+    // Alice is known to be registered, so the transfer is tried. It fails, and the claimed
+    // balance becomes withdrawable.
     let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
     assert_eq!(res.0, 0);
     let lockups = e.get_account_lockups(&users.alice);
     assert_eq!(lockups[0].1.total_balance, amount);
-    assert_eq!(lockups[0].1.claimed_balance, amount / 3);
-    assert_eq!(lockups[0].1.unclaimed_balance, amount / 6);
+    assert_eq!(lockups[0].1.claimed_balance, amount / 2);
+    assert_eq!(lockups[0].1.unclaimed_balance, 0);
+    assert_eq!(e.get_withdrawable(&users.alice), amount / 6);
+
+    // Trying to claim again, the registration check fails before transferring anything.
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, 0);
+    assert_eq!(e.ft_balance_of(&users.alice), 0);
+    assert_eq!(e.get_withdrawable(&users.alice), amount / 6);
 
     // Claim again but with storage deposit
     ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
@@ -230,9 +238,9 @@ fn test_lockup_cliff_amazon() {
     e.set_time_sec(GENESIS_TIMESTAMP_SEC);
     let lockups = e.get_account_lockups(&users.alice);
     assert!(lockups.is_empty());
-    let lockup = Lockup {
-        account_id: users.alice.valid_account_id(),
-        schedule: Schedule(vec![
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
             Checkpoint {
                 timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC - 1,
                 balance: 0,
@@ -254,9 +262,7 @@ fn test_lockup_cliff_amazon() {
                 balance: amount,
             },
         ]),
-        claimed_balance: 0,
-        termination_config: None,
-    };
+    );
     let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
     assert_eq!(balance.0, amount);
     let lockups = e.get_account_lockups(&users.alice);
@@ -341,3 +347,1953 @@ fn test_lockup_cliff_amazon() {
     assert_eq!(lockup.claimed_balance, amount);
     assert_eq!(lockup.unclaimed_balance, 0);
 }
+
+#[test]
+fn test_two_step_ownership_transfer() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    assert_eq!(e.get_owner(), OWNER_ID);
+
+    // Only the owner can propose.
+    let res = users.alice.function_call(
+        e.contract
+            .contract
+            .propose_owner(users.alice.valid_account_id()),
+        DEFAULT_GAS,
+        1,
+    );
+    assert!(!res.is_ok());
+
+    e.owner
+        .function_call(
+            e.contract
+                .contract
+                .propose_owner(users.alice.valid_account_id()),
+            DEFAULT_GAS,
+            1,
+        )
+        .assert_success();
+    assert_eq!(e.get_owner(), OWNER_ID);
+    assert_eq!(e.get_pending_owner(), Some(users.alice.account_id.clone()));
+
+    // Only the pending owner can accept.
+    let res = users
+        .bob
+        .function_call(e.contract.contract.accept_ownership(), DEFAULT_GAS, 1);
+    assert!(!res.is_ok());
+
+    users
+        .alice
+        .function_call(e.contract.contract.accept_ownership(), DEFAULT_GAS, 1)
+        .assert_success();
+    assert_eq!(e.get_owner(), users.alice.account_id);
+    assert_eq!(e.get_pending_owner(), None);
+
+    // The proposal can be cancelled.
+    users
+        .alice
+        .function_call(
+            e.contract
+                .contract
+                .propose_owner(users.bob.valid_account_id()),
+            DEFAULT_GAS,
+            1,
+        )
+        .assert_success();
+    users
+        .alice
+        .function_call(e.contract.contract.cancel_owner_proposal(), DEFAULT_GAS, 1)
+        .assert_success();
+    assert_eq!(e.get_pending_owner(), None);
+    let res = users
+        .bob
+        .function_call(e.contract.contract.accept_ownership(), DEFAULT_GAS, 1);
+    assert!(!res.is_ok());
+}
+
+#[test]
+fn test_pause() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(10000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+
+    e.pause(PauseKind::Deposit).assert_success();
+    assert!(e.get_pause_state().deposit);
+    let owner_balance = e.ft_balance_of(&e.owner);
+    // The deposit is refunded.
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, 0);
+    assert_eq!(e.ft_balance_of(&e.owner), owner_balance);
+    assert!(e.get_account_lockups(&users.alice).is_empty());
+
+    e.unpause(PauseKind::Deposit).assert_success();
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC);
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+    e.pause(PauseKind::Claim).assert_success();
+    assert!(!e.claim(&users.alice).is_ok());
+    assert_eq!(e.ft_balance_of(&users.alice), 0);
+
+    e.unpause(PauseKind::Claim).assert_success();
+    assert_eq!(e.get_pause_state(), PauseState::default());
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount);
+}
+
+#[test]
+fn test_blacklist_freeze() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+
+    // Freeze at 1/3 until 1/2.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 3);
+    e.add_to_blacklist(
+        &users.alice,
+        Some(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2),
+        true,
+    )
+    .assert_success();
+
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC * 5 / 12);
+    assert!(!e.claim(&users.alice).is_ok());
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups[0].1.unclaimed_balance, amount / 3);
+
+    // The entry has expired, the schedule is delayed by 1/6 of the year.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount / 3);
+
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC);
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups[0].1.unclaimed_balance, amount / 2);
+
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC + ONE_YEAR_SEC / 6);
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount * 2 / 3);
+    assert_eq!(e.ft_balance_of(&users.alice), amount);
+
+    // The lifted freeze doesn't delay the lockups created afterwards.
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC * 2,
+                balance: amount,
+            },
+        ]),
+    );
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount / 6);
+}
+
+#[test]
+fn test_migrate_legacy_lockups() {
+    let e = Env::init_legacy();
+    let users = Users::init(&e);
+    let amount = d(1000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    for user in [&users.alice, &users.alice, &users.bob] {
+        let lockup = new_lockup(
+            user.valid_account_id(),
+            Schedule(vec![
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC,
+                    balance: 0,
+                },
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                    balance: amount,
+                },
+            ]),
+        );
+        e.add_lockup(&e.owner, amount, &lockup).assert_success();
+    }
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+    ft_storage_deposit(&users.bob, TOKEN_ID, &users.bob.account_id);
+
+    // The lockups are read from the legacy layout until they are migrated.
+    e.upgrade().assert_success();
+    assert_eq!(e.get_num_legacy_lockups(), 3);
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(
+        lockups.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+        vec![0, 1]
+    );
+    assert_eq!(lockups[0].1.token_account_id, TOKEN_ID.to_string());
+    assert_eq!(lockups[0].1.total_balance, amount);
+    // The balance history starts at the upgrade.
+    assert_eq!(
+        e.get_locked_balance_at(&users.alice, GENESIS_TIMESTAMP_SEC - 1),
+        0
+    );
+    assert_eq!(
+        e.get_locked_balance_at(&users.alice, GENESIS_TIMESTAMP_SEC),
+        amount * 2
+    );
+
+    // The depositor doesn't pay for the migration of a legacy account.
+    storage_deposit(&e.owner, FT_LOCKUP_ID, OWNER_ID, to_yocto("1"));
+    let mut storage_costs = vec![];
+    for user in [&users.charlie, &users.bob] {
+        let lockup = new_lockup(
+            user.valid_account_id(),
+            Schedule(vec![
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC,
+                    balance: 0,
+                },
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                    balance: amount,
+                },
+            ]),
+        );
+        let initial_available = e.storage_available(&e.owner).unwrap();
+        e.add_lockup(&e.owner, amount, &lockup).assert_success();
+        storage_costs.push(initial_available - e.storage_available(&e.owner).unwrap());
+    }
+    assert!(storage_costs[1] <= storage_costs[0]);
+    assert_eq!(e.get_num_legacy_lockups(), 3);
+
+    // A claim migrates the account and seeds its balance history.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount);
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups[1].1.claimed_balance, amount / 2);
+    assert_eq!(
+        e.get_locked_balance_at(&users.alice, GENESIS_TIMESTAMP_SEC),
+        amount * 2
+    );
+    assert_eq!(
+        e.get_locked_balance_at(&users.alice, GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2),
+        amount
+    );
+
+    // The batches skip the migrated lockups, and the last one drops the legacy layout.
+    assert_eq!(e.migrate_lockups(&users.charlie, 2), 1);
+    assert_eq!(e.get_lockup(1).claimed_balance, amount / 2);
+    assert_eq!(e.get_lockup(2).claimed_balance, 0);
+    assert_eq!(e.migrate_lockups(&users.charlie, 10), 0);
+    assert_eq!(e.get_num_legacy_lockups(), 0);
+    assert_eq!(
+        e.get_locked_balance_at(&users.bob, GENESIS_TIMESTAMP_SEC),
+        amount * 2
+    );
+    let res: WrappedBalance = e.claim(&users.bob).unwrap_json();
+    assert_eq!(res.0, amount);
+    assert_eq!(e.get_lockup(2).claimed_balance, amount / 2);
+}
+
+#[test]
+fn test_paginated_views() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(1000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    for _ in 0..3 {
+        e.add_lockup(&e.owner, amount, &lockup).assert_success();
+    }
+
+    let num_lockups: u32 = e
+        .near
+        .view_method_call(
+            e.contract
+                .contract
+                .get_num_account_lockups(users.alice.valid_account_id()),
+        )
+        .unwrap_json();
+    assert_eq!(num_lockups, 3);
+
+    let lockups = e.get_account_lockups_paged(&users.alice, Some(1), Some(10));
+    assert_eq!(
+        lockups.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    let lockups = e.get_account_lockups_paged(&users.alice, Some(0), Some(1));
+    assert_eq!(lockups[0].0, 0);
+    assert!(e
+        .get_account_lockups_paged(&users.alice, Some(5), None)
+        .is_empty());
+
+    let whitelist: Vec<String> = e
+        .near
+        .view_method_call(e.contract.contract.get_deposit_whitelist(None, Some(1)))
+        .unwrap_json();
+    assert_eq!(whitelist, vec![OWNER_ID.to_string()]);
+    let is_whitelisted: bool = e
+        .near
+        .view_method_call(
+            e.contract
+                .contract
+                .is_whitelisted(users.alice.valid_account_id()),
+        )
+        .unwrap_json();
+    assert!(!is_whitelisted);
+
+    e.add_to_blacklist(&users.bob, None, false).assert_success();
+    let is_blacklisted: bool = e
+        .near
+        .view_method_call(
+            e.contract
+                .contract
+                .is_blacklisted(users.bob.valid_account_id()),
+        )
+        .unwrap_json();
+    assert!(is_blacklisted);
+}
+
+#[test]
+fn test_lockups_paged() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(1000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    for user in [&users.alice, &users.bob, &users.alice, &users.charlie].iter() {
+        let lockup = new_lockup(
+            user.valid_account_id(),
+            Schedule(vec![
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC,
+                    balance: 0,
+                },
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                    balance: amount,
+                },
+            ]),
+        );
+        e.add_lockup(&e.owner, amount, &lockup).assert_success();
+    }
+
+    // `limit` is a page size.
+    let page = e.get_lockups_paged(Some(1), Some(2), None);
+    assert_eq!(
+        page.lockups
+            .iter()
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    assert_eq!(page.next_index, Some(3));
+    let page = e.get_lockups_paged(page.next_index, Some(2), None);
+    assert_eq!(page.lockups.len(), 1);
+    assert_eq!(page.next_index, None);
+
+    let page = e.get_lockups_paged(
+        None,
+        None,
+        Some(LockupFilter {
+            account_id: Some(users.alice.valid_account_id()),
+            status: Some(LockupStatus::Active),
+            ..Default::default()
+        }),
+    );
+    assert_eq!(
+        page.lockups
+            .iter()
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>(),
+        vec![0, 2]
+    );
+    assert_eq!(page.next_index, None);
+
+    let page = e.get_lockups_paged(
+        None,
+        None,
+        Some(LockupFilter {
+            terminable: Some(true),
+            ..Default::default()
+        }),
+    );
+    assert!(page.lockups.is_empty());
+
+    // A zero page size would never advance the cursor.
+    assert!(e
+        .near
+        .view_method_call(e.contract.contract.get_lockups_paged(None, Some(0), None))
+        .is_err());
+}
+
+#[test]
+fn test_lockup_storage() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(1000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    assert_eq!(e.storage_available(&users.alice), None);
+    let initial_available = e.storage_available(&e.owner).unwrap();
+
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+    let available = e.storage_available(&e.owner).unwrap();
+    assert!(available < initial_available);
+
+    // Withdraw all available storage balance.
+    e.owner
+        .call(
+            e.contract.user_account.account_id.clone(),
+            "storage_withdraw",
+            b"{}",
+            DEFAULT_GAS,
+            1,
+        )
+        .assert_success();
+    assert_eq!(e.storage_available(&e.owner), Some(0));
+
+    // Not enough storage, the tokens are returned.
+    let owner_balance = e.ft_balance_of(&e.owner);
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, 0);
+    assert_eq!(e.ft_balance_of(&e.owner), owner_balance);
+    assert_eq!(e.get_account_lockups(&users.alice).len(), 1);
+
+    // The storage of the account lockups index is released after the final claim.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC);
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount);
+    assert!(e.storage_available(&e.owner).unwrap() > 0);
+}
+
+#[test]
+fn test_lockup_compaction() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(1000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2,
+                balance: amount / 2,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    e.add_lockup(&e.owner, amount, &lockup).assert_success();
+    e.add_lockup(&e.owner, amount, &lockup).assert_success();
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+
+    // Finish the lockup #0 before the compaction is enabled.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC);
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount * 2);
+    assert!(!e.get_lockup(0).archived);
+
+    let res = e
+        .owner
+        .function_call(e.contract.contract.compact_lockups(vec![0]), DEFAULT_GAS, 0);
+    assert!(!res.is_ok());
+
+    e.owner
+        .function_call(
+            e.contract.contract.set_compaction_enabled(true),
+            DEFAULT_GAS,
+            1,
+        )
+        .assert_success();
+    let available = e.storage_available(&e.owner).unwrap();
+    let num_archived: u32 = users
+        .bob
+        .function_call(
+            e.contract.contract.compact_lockups(vec![0, 1, 2]),
+            DEFAULT_GAS,
+            0,
+        )
+        .unwrap_json();
+    assert_eq!(num_archived, 2);
+    assert!(e.storage_available(&e.owner).unwrap() > available);
+
+    let lockup = e.get_lockup(0);
+    assert!(lockup.archived);
+    assert_eq!(lockup.total_balance, amount);
+    assert_eq!(lockup.claimed_balance, amount);
+    assert_eq!(lockup.unclaimed_balance, 0);
+    assert_eq!(
+        lockup.schedule.0.last().unwrap().timestamp,
+        GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC
+    );
+}
+
+#[test]
+fn test_lockup_indices_gas() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    deploy_legacy_lockup(&e.near, &e.owner, LEGACY_FT_LOCKUP_ID);
+    let amount = d(1, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC * 10,
+                balance: amount,
+            },
+        ]),
+    );
+    let msg = serde_json::to_string(&lockup).unwrap();
+
+    // The legacy layout rewrites all active indices of Alice on every new lockup, and the
+    // current one only writes the new index. Measure the deposit cost as the number of active
+    // lockups grows from 10 to 40.
+    let mut deposit_gas = vec![];
+    for num_lockups in 1..=40 {
+        let res = e.add_lockup(&e.owner, amount, &lockup);
+        res.assert_success();
+        let gas = total_gas_burnt(&res);
+        let legacy_res = ft_transfer_call(&e.owner, TOKEN_ID, LEGACY_FT_LOCKUP_ID, amount, &msg);
+        legacy_res.assert_success();
+        if num_lockups == 10 || num_lockups == 40 {
+            deposit_gas.push((gas, total_gas_burnt(&legacy_res)));
+        }
+    }
+    assert_eq!(e.get_account_lockups(&users.alice).len(), 40);
+    let gas_per_index = (deposit_gas[1].0 - deposit_gas[0].0) / 30;
+    let legacy_gas_per_index = (deposit_gas[1].1 - deposit_gas[0].1) / 30;
+    assert!(
+        gas_per_index < legacy_gas_per_index,
+        "Gas per active index: {}, legacy: {}",
+        gas_per_index,
+        legacy_gas_per_index
+    );
+}
+
+#[test]
+fn test_claim_gas() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    storage_deposit(&e.owner, FT_LOCKUP_ID, OWNER_ID, to_yocto("10"));
+    let amount = d(1, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let schedule = |start: TimestampSec| {
+        Schedule(vec![
+            Checkpoint {
+                timestamp: start,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: start + 1,
+                balance: amount,
+            },
+        ])
+    };
+
+    // Every account has one claimable lockup, and the rest are still locked.
+    // The first claim checks the registration with the token, so it isn't measured.
+    let mut claim_gas = vec![];
+    for (user, num_lockups) in [(&users.alice, 1), (&users.bob, 50), (&users.charlie, 200)] {
+        ft_storage_deposit(user, TOKEN_ID, &user.account_id);
+        let lockup = new_lockup(user.valid_account_id(), schedule(GENESIS_TIMESTAMP_SEC));
+        e.add_lockup(&e.owner, amount, &lockup).assert_success();
+        e.set_time_sec(GENESIS_TIMESTAMP_SEC + 1);
+        let claimed: WrappedBalance = e.claim(user).unwrap_json();
+        assert_eq!(claimed.0, amount);
+        e.add_lockup(&e.owner, amount, &lockup).assert_success();
+        let lockup = new_lockup(
+            user.valid_account_id(),
+            schedule(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC),
+        );
+        for _ in 1..num_lockups {
+            e.add_lockup(&e.owner, amount, &lockup).assert_success();
+        }
+        let res = e.claim_with_gas(user, MAX_GAS);
+        let claimed: WrappedBalance = res.unwrap_json();
+        assert_eq!(claimed.0, amount);
+        claim_gas.push(total_gas_burnt(&res));
+    }
+
+    // The claim cost grows by a bounded amount per scanned lockup.
+    for (i, num_lockups) in [(1, 50), (2, 200)] {
+        let gas_per_lockup = (claim_gas[i] - claim_gas[0]) / (num_lockups - 1);
+        assert!(
+            gas_per_lockup < T_GAS,
+            "Claim gas: {:?}, per lockup: {}",
+            claim_gas,
+            gas_per_lockup
+        );
+    }
+}
+
+#[test]
+fn test_claim_gas_multi_token() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(1, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    e.deploy_token(TOKEN2_ID);
+    ft_storage_deposit(&e.owner, TOKEN2_ID, FT_LOCKUP_ID);
+    e.add_to_token_whitelist(&e.owner, TOKEN2_ID)
+        .assert_success();
+    let schedule = Schedule(vec![
+        Checkpoint {
+            timestamp: GENESIS_TIMESTAMP_SEC,
+            balance: 0,
+        },
+        Checkpoint {
+            timestamp: GENESIS_TIMESTAMP_SEC + 1,
+            balance: amount,
+        },
+    ]);
+
+    let num_lockups = 4;
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+    ft_storage_deposit(&users.alice, TOKEN2_ID, &users.alice.account_id);
+    ft_storage_deposit(&users.bob, TOKEN_ID, &users.bob.account_id);
+    for token_id in [TOKEN_ID, TOKEN2_ID] {
+        let lockup = Lockup {
+            token_account_id: token_id.to_string(),
+            ..new_lockup(users.alice.valid_account_id(), schedule.clone())
+        };
+        for _ in 0..num_lockups {
+            e.add_lockup_with_token(&e.owner, token_id, amount, &lockup)
+                .assert_success();
+        }
+    }
+    // Bob's registration with the token is checked by the first claim, so the next ones
+    // only claim.
+    let lockup = new_lockup(users.bob.valid_account_id(), schedule.clone());
+    e.add_lockup(&e.owner, amount, &lockup).assert_success();
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + 1);
+    let res: WrappedBalance = e.claim(&users.bob).unwrap_json();
+    assert_eq!(res.0, amount);
+    let num_bob_lockups = 30;
+    for _ in 0..num_bob_lockups {
+        e.add_lockup(&e.owner, amount, &lockup).assert_success();
+    }
+
+    // All finished lockups of both tokens are claimed and removed by a single claim.
+    let res: WrappedBalance = e.claim_with_gas(&users.alice, MAX_GAS).unwrap_json();
+    assert_eq!(res.0, amount * num_lockups * 2);
+    assert!(e.get_account_lockups(&users.alice).is_empty());
+    assert_eq!(e.ft_balance_of(&users.alice), amount * num_lockups);
+    assert_eq!(
+        e.ft_balance_of_token(TOKEN2_ID, &users.alice),
+        amount * num_lockups
+    );
+
+    // With less gas, the lockups that don't fit are left for the next claims.
+    let mut num_claims = 0;
+    let mut claimed = 0;
+    while !e.get_account_lockups(&users.bob).is_empty() {
+        let res: WrappedBalance = e.claim_with_gas(&users.bob, CLAIM_GAS).unwrap_json();
+        assert!(res.0 > 0);
+        claimed += res.0;
+        num_claims += 1;
+    }
+    assert!(num_claims > 1);
+    assert_eq!(claimed, amount * num_bob_lockups);
+    assert_eq!(e.ft_balance_of(&users.bob), amount * (num_bob_lockups + 1));
+}
+
+#[test]
+fn test_registered_schedule() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let shape = Schedule(vec![
+        Checkpoint {
+            timestamp: GENESIS_TIMESTAMP_SEC,
+            balance: 0,
+        },
+        Checkpoint {
+            timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+            balance: 100,
+        },
+    ]);
+    let schedule_id: Base58CryptoHash = e
+        .owner
+        .function_call(
+            e.contract.contract.register_schedule(shape.clone()),
+            DEFAULT_GAS,
+            1,
+        )
+        .unwrap_json();
+
+    let lockup = Lockup {
+        schedule_id: Some(schedule_id),
+        ..new_lockup(users.alice.valid_account_id(), Schedule::default())
+    };
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups[0].1.schedule_id, Some(schedule_id));
+    assert_eq!(lockups[0].1.total_balance, amount);
+    assert_eq!(lockups[0].1.schedule.0[1].balance, amount);
+
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 3);
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups[0].1.unclaimed_balance, amount / 3);
+
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount / 3);
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups[0].1.schedule_id, Some(schedule_id));
+    assert_eq!(lockups[0].1.claimed_balance, amount / 3);
+}
+
+#[test]
+fn test_multi_token_lockups() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    e.deploy_token(TOKEN2_ID);
+    ft_storage_deposit(&e.owner, TOKEN2_ID, FT_LOCKUP_ID);
+
+    let lockup = Lockup {
+        token_account_id: TOKEN2_ID.to_string(),
+        ..new_lockup(
+            users.alice.valid_account_id(),
+            Schedule(vec![
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC,
+                    balance: 0,
+                },
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                    balance: amount,
+                },
+            ]),
+        )
+    };
+    // Not whitelisted yet.
+    let res = e.add_lockup_with_token(&e.owner, TOKEN2_ID, amount, &lockup);
+    let balance: WrappedBalance = res.unwrap_json();
+    assert_eq!(balance.0, 0);
+    assert!(e.get_account_lockups(&users.alice).is_empty());
+
+    e.add_to_token_whitelist(&e.owner, TOKEN2_ID)
+        .assert_success();
+    let res = e.add_lockup_with_token(&e.owner, TOKEN2_ID, amount, &lockup);
+    let balance: WrappedBalance = res.unwrap_json();
+    assert_eq!(balance.0, amount);
+    let res = e.add_lockup(&e.owner, amount, &lockup);
+    let balance: WrappedBalance = res.unwrap_json();
+    assert_eq!(balance.0, amount);
+
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups.len(), 2);
+    assert_eq!(lockups[0].1.token_account_id, TOKEN2_ID.to_string());
+    assert_eq!(lockups[1].1.token_account_id, TOKEN_ID.to_string());
+
+    // Both tokens are transferred by a single claim.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+    ft_storage_deposit(&users.alice, TOKEN2_ID, &users.alice.account_id);
+    let res: WrappedBalance = e.claim_with_gas(&users.alice, MAX_GAS).unwrap_json();
+    assert_eq!(res.0, amount);
+    assert_eq!(e.ft_balance_of(&users.alice), amount / 2);
+    assert_eq!(e.ft_balance_of_token(TOKEN2_ID, &users.alice), amount / 2);
+
+    // Only the lockups of the given token are claimed.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC);
+    let res: WrappedBalance = e.claim_token(&users.alice, TOKEN2_ID).unwrap_json();
+    assert_eq!(res.0, amount / 2);
+    assert_eq!(e.ft_balance_of(&users.alice), amount / 2);
+    assert_eq!(e.ft_balance_of_token(TOKEN2_ID, &users.alice), amount);
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups.len(), 1);
+    assert_eq!(lockups[0].1.token_account_id, TOKEN_ID.to_string());
+}
+
+#[test]
+fn test_near_lockup() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = to_yocto("100");
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = Lockup {
+        token_account_id: NEAR_TOKEN_ID.to_string(),
+        ..new_lockup(
+            users.alice.valid_account_id(),
+            Schedule(vec![
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC,
+                    balance: 0,
+                },
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                    balance: amount,
+                },
+            ]),
+        )
+    };
+    // The attached deposit doesn't match the schedule.
+    assert!(!e.create_near_lockup(&e.owner, amount - 1, &lockup).is_ok());
+    // Not in deposit whitelist.
+    assert!(!e.create_near_lockup(&users.bob, amount, &lockup).is_ok());
+
+    let index: LockupIndex = e
+        .create_near_lockup(&e.owner, amount, &lockup)
+        .unwrap_json();
+    assert_eq!(index, 0);
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups[0].1.token_account_id, NEAR_TOKEN_ID.to_string());
+    assert_eq!(lockups[0].1.total_balance, amount);
+    assert_eq!(e.get_near_locked_balance(), amount);
+
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+    let initial_balance = users.alice.account().unwrap().amount;
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount / 2);
+    let balance = users.alice.account().unwrap().amount;
+    assert!(balance > initial_balance + amount / 2 - to_yocto("0.1"));
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups[0].1.claimed_balance, amount / 2);
+    assert_eq!(e.get_near_locked_balance(), amount / 2);
+
+    // The NEAR lockups can be claimed by their token ID.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC);
+    let res: WrappedBalance = e.claim_token(&users.alice, NEAR_TOKEN_ID).unwrap_json();
+    assert_eq!(res.0, amount / 2);
+    assert_eq!(e.get_near_locked_balance(), 0);
+}
+
+#[test]
+fn test_voting_power() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    for user in [&users.alice, &users.bob] {
+        let lockup = new_lockup(
+            user.valid_account_id(),
+            Schedule(vec![
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC,
+                    balance: 0,
+                },
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                    balance: amount,
+                },
+            ]),
+        );
+        let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+        assert_eq!(balance.0, amount);
+    }
+
+    // Not configured.
+    assert!(e
+        .near
+        .view_method_call(
+            e.contract
+                .contract
+                .get_voting_power(users.alice.valid_account_id(), None)
+        )
+        .is_err());
+
+    e.set_voting_power_config(VotingPowerWeighting::Flat)
+        .assert_success();
+    assert_eq!(e.get_voting_power(&users.alice, None), amount);
+    let half_year = GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2;
+    assert_eq!(
+        e.get_voting_power(&users.alice, Some(half_year)),
+        amount / 2
+    );
+    assert_eq!(e.get_total_voting_power(Some(half_year)), amount);
+    let page = e.get_total_voting_power_page(Some(half_year), None, Some(1));
+    assert_eq!(page.voting_power.0, amount / 2);
+    assert_eq!(page.next_index, Some(1));
+    let page = e.get_total_voting_power_page(Some(half_year), page.next_index, Some(1));
+    assert_eq!(page.voting_power.0, amount / 2);
+    assert_eq!(page.next_index, None);
+    assert_eq!(e.get_voting_power(&users.charlie, None), 0);
+
+    e.set_voting_power_config(VotingPowerWeighting::Decaying {
+        max_duration: ONE_YEAR_SEC,
+    })
+    .assert_success();
+    assert_eq!(e.get_voting_power(&users.alice, None), amount);
+    // Half of the balance is locked for the remaining half of the max duration.
+    assert_eq!(
+        e.get_voting_power(&users.alice, Some(half_year)),
+        amount / 4
+    );
+    assert_eq!(e.get_total_voting_power(Some(half_year)), amount / 2);
+    assert_eq!(
+        e.get_voting_power(&users.alice, Some(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC)),
+        0
+    );
+}
+
+#[test]
+fn test_balance_history() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+
+    // A failed claim doesn't change the history.
+    let claim_timestamp = GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2;
+    e.set_time_sec(claim_timestamp - ONE_DAY_SEC);
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, 0);
+
+    e.set_time_sec(claim_timestamp);
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount / 2);
+
+    assert_eq!(
+        e.get_locked_balance_at(&users.alice, GENESIS_TIMESTAMP_SEC - 1),
+        0
+    );
+    assert_eq!(
+        e.get_locked_balance_at(&users.alice, GENESIS_TIMESTAMP_SEC),
+        amount
+    );
+    assert_eq!(
+        e.get_locked_balance_at(&users.alice, claim_timestamp - 1),
+        amount
+    );
+    assert_eq!(
+        e.get_locked_balance_at(&users.alice, claim_timestamp),
+        amount / 2
+    );
+    assert_eq!(
+        e.get_locked_balance_at(&users.alice, claim_timestamp + ONE_DAY_SEC),
+        amount / 2
+    );
+}
+
+#[test]
+fn test_stake_locked_balance() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+
+    // Not in farm whitelist yet.
+    assert!(!e.stake(&users.alice, 0, None).is_ok());
+    // 10% reward.
+    let farm = e.deploy_farm(1000);
+    e.ft_transfer(&e.owner, FARM_ID, amount);
+
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 4);
+    // Only the beneficiary can stake.
+    assert!(!e.stake(&users.bob, 0, None).is_ok());
+    // Only the locked balance can be staked.
+    assert!(!e.stake(&users.alice, 0, Some(amount)).is_ok());
+    let staked_amount: WrappedBalance = e.stake(&users.alice, 0, None).unwrap_json();
+    assert_eq!(staked_amount.0, amount * 3 / 4);
+    assert_eq!(e.get_lockup_stake(0).unwrap().balance, amount * 3 / 4);
+    assert_eq!(e.ft_balance_of(&farm), amount + amount * 3 / 4);
+
+    // The staked balance can't be claimed.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount / 4);
+
+    let unstaked_amount: WrappedBalance = e.unstake(&users.alice, 0, None).unwrap_json();
+    assert_eq!(unstaked_amount.0, amount * 3 / 4);
+    assert!(e.get_lockup_stake(0).is_none());
+    let reward = amount * 3 / 4 / 10;
+    assert_eq!(e.ft_balance_of(&users.alice), amount / 4 + reward);
+
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount / 4);
+    assert_eq!(e.ft_balance_of(&users.alice), amount / 2 + reward);
+}
+
+#[test]
+fn test_terminate_staked_lockup() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = Lockup {
+        termination_config: Some(TerminationConfig {
+            terminator_id: e.owner.valid_account_id(),
+            vesting_schedule: None,
+        }),
+        ..new_lockup(
+            users.alice.valid_account_id(),
+            Schedule(vec![
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC,
+                    balance: 0,
+                },
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                    balance: amount,
+                },
+            ]),
+        )
+    };
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+    let farm = e.deploy_farm(0);
+
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 4);
+    let staked_amount: WrappedBalance = e.stake(&users.alice, 0, None).unwrap_json();
+    assert_eq!(staked_amount.0, amount * 3 / 4);
+
+    // Staking doesn't block the termination. The held part of the unvested balance is
+    // transferred, and the staked part is owed to the terminator.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+    let owner_balance = e.ft_balance_of(&e.owner);
+    let res: WrappedBalance = e.terminate(&e.owner, 0).unwrap_json();
+    assert_eq!(res.0, amount / 4);
+    assert_eq!(e.ft_balance_of(&e.owner), owner_balance + amount / 4);
+    let stake = e.get_lockup_stake(0).unwrap();
+    assert_eq!(stake.balance, amount * 3 / 4);
+    assert_eq!(stake.terminated_balance, amount / 4);
+    assert_eq!(stake.terminator_id, Some(e.owner.account_id.clone()));
+
+    // The vested balance is staked.
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, 0);
+
+    // The terminator can unstake, and the unstaked balance pays the terminator first.
+    let unstaked_amount: WrappedBalance = e.unstake(&e.owner, 0, None).unwrap_json();
+    assert_eq!(unstaked_amount.0, amount * 3 / 4);
+    assert!(e.get_lockup_stake(0).is_none());
+    assert_eq!(e.ft_balance_of(&e.owner), owner_balance + amount / 2);
+    assert_eq!(e.ft_balance_of(&farm), 0);
+
+    let res: WrappedBalance = e.claim(&users.alice).unwrap_json();
+    assert_eq!(res.0, amount / 2);
+    assert_eq!(e.ft_balance_of(&users.alice), amount / 2);
+}
+
+#[test]
+fn test_lockup_created_notification() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    // Alice has no contract, so the notification fails, but the lockup is created.
+    let res = e.add_lockup_with_notify(&e.owner, amount, &lockup, "vesting");
+    let balance: WrappedBalance = res.unwrap_json();
+    assert_eq!(balance.0, amount);
+    let logs: Vec<String> = res
+        .promise_results()
+        .into_iter()
+        .flatten()
+        .flat_map(|result| result.logs().clone())
+        .collect();
+    assert!(logs
+        .iter()
+        .any(|log| log.contains("Failed to notify alice.near about the lockup #0")));
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups.len(), 1);
+    assert_eq!(lockups[0].1.total_balance, amount);
+}
+
+#[test]
+fn test_lockup_metadata() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    e.owner
+        .function_call(
+            e.contract
+                .contract
+                .add_to_deposit_whitelist(users.bob.valid_account_id()),
+            DEFAULT_GAS,
+            1,
+        )
+        .assert_success();
+    storage_deposit(
+        &users.bob,
+        FT_LOCKUP_ID,
+        &users.bob.account_id,
+        to_yocto("1"),
+    );
+    ft_storage_deposit(&users.bob, TOKEN_ID, &users.bob.account_id);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.ft_transfer(&e.owner, &users.bob.account_id, amount);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = |category: &str| Lockup {
+        memo: Some("Grant #1".to_string()),
+        category: Some(category.to_string()),
+        ..new_lockup(
+            users.alice.valid_account_id(),
+            Schedule(vec![
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC,
+                    balance: 0,
+                },
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                    balance: amount,
+                },
+            ]),
+        )
+    };
+    // Bounded category.
+    let res = e.add_lockup(&e.owner, amount, &lockup(&"a".repeat(65)));
+    let balance: WrappedBalance = res.unwrap_json();
+    assert_eq!(balance.0, 0);
+
+    let balance: WrappedBalance = e
+        .add_lockup(&e.owner, amount, &lockup("team"))
+        .unwrap_json();
+    assert_eq!(balance.0, amount);
+    let balance: WrappedBalance = e
+        .add_lockup(&users.bob, amount, &lockup("seed"))
+        .unwrap_json();
+    assert_eq!(balance.0, amount);
+
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups[0].1.creator_id, Some(OWNER_ID.to_string()));
+    assert_eq!(lockups[0].1.created_at, Some(GENESIS_TIMESTAMP_SEC));
+    assert_eq!(lockups[0].1.memo, Some("Grant #1".to_string()));
+    assert_eq!(lockups[0].1.category, Some("team".to_string()));
+    assert_eq!(lockups[1].1.creator_id, Some(users.bob.account_id.clone()));
+
+    let page = e.get_lockups_paged(
+        None,
+        None,
+        Some(LockupFilter {
+            category: Some("seed".to_string()),
+            ..Default::default()
+        }),
+    );
+    assert_eq!(page.lockups.len(), 1);
+    assert_eq!(page.lockups[0].0, 1);
+
+    let page = e.get_lockups_paged(
+        None,
+        None,
+        Some(LockupFilter {
+            creator_id: Some(e.owner.valid_account_id()),
+            ..Default::default()
+        }),
+    );
+    assert_eq!(page.lockups.len(), 1);
+    assert_eq!(page.lockups[0].0, 0);
+}
+
+#[test]
+fn test_cancel_lockup() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_DAY_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    for _ in 0..2 {
+        let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+        assert_eq!(balance.0, amount);
+    }
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(
+        lockups[0].1.cancellable_until,
+        Some(GENESIS_TIMESTAMP_SEC + ONE_DAY_SEC)
+    );
+    let owner_balance = e.ft_balance_of(&e.owner);
+
+    // Only the creator can cancel.
+    assert!(!e.cancel_lockup(&users.alice, 0).is_ok());
+    let res: WrappedBalance = e.cancel_lockup(&e.owner, 0).unwrap_json();
+    assert_eq!(res.0, amount);
+    assert_eq!(e.ft_balance_of(&e.owner), owner_balance + amount);
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups.len(), 1);
+    assert_eq!(lockups[0].0, 1);
+    assert_eq!(e.get_lockup(0).total_balance, 0);
+
+    // The window has passed once the schedule starts unlocking.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_DAY_SEC);
+    assert!(!e.cancel_lockup(&e.owner, 1).is_ok());
+    assert_eq!(e.ft_balance_of(&e.owner), owner_balance + amount);
+
+    // The deadline can't keep the lockup cancellable after it starts unlocking.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = Lockup {
+        cancellation_deadline: Some(GENESIS_TIMESTAMP_SEC + 10 * ONE_YEAR_SEC),
+        ..lockup
+    };
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, 0);
+    assert_eq!(e.get_account_lockups(&users.alice).len(), 1);
+
+    // Past the first non-zero checkpoint, the creator can't cancel, and Alice keeps it all.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+    assert!(!e.cancel_lockup(&e.owner, 1).is_ok());
+    assert_eq!(e.get_lockup(1).total_balance, amount);
+    assert_eq!(e.ft_balance_of(&e.owner), owner_balance + amount);
+
+    // The lockup doesn't start unlocking while the beneficiary is frozen.
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2 + ONE_DAY_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+    e.add_to_blacklist(&users.alice, None, true)
+        .assert_success();
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2 + ONE_DAY_SEC * 2);
+    let res: WrappedBalance = e.cancel_lockup(&e.owner, 2).unwrap_json();
+    assert_eq!(res.0, amount);
+    assert_eq!(e.ft_balance_of(&e.owner), owner_balance + amount);
+}
+
+#[test]
+fn test_claw_back() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+    assert_eq!(e.get_lockup(0).claim_deadline, None);
+
+    e.owner
+        .function_call(
+            e.contract
+                .contract
+                .set_treasury(Some(users.bob.valid_account_id())),
+            DEFAULT_GAS,
+            1,
+        )
+        .assert_success();
+    e.owner
+        .function_call(
+            e.contract.contract.set_claim_period(Some(60 * ONE_DAY_SEC)),
+            DEFAULT_GAS,
+            1,
+        )
+        .assert_success();
+    let claim_deadline = GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC + 60 * ONE_DAY_SEC;
+    let lockup_view = e.get_lockup(0);
+    assert_eq!(lockup_view.claim_deadline, Some(claim_deadline));
+    assert!(!lockup_view.expires_soon);
+
+    e.set_time_sec(claim_deadline - ONE_DAY_SEC);
+    assert!(e.get_lockup(0).expires_soon);
+    // Only the treasury after the deadline.
+    assert!(!e.claw_back(&users.bob, 0).is_ok());
+    e.set_time_sec(claim_deadline);
+    assert!(!e.claw_back(&users.charlie, 0).is_ok());
+
+    ft_storage_deposit(&users.bob, TOKEN_ID, &users.bob.account_id);
+    e.pause(PauseKind::Terminate).assert_success();
+    assert!(!e.claw_back(&users.bob, 0).is_ok());
+    e.unpause(PauseKind::Terminate).assert_success();
+    let res: WrappedBalance = e.claw_back(&users.bob, 0).unwrap_json();
+    assert_eq!(res.0, amount);
+    assert_eq!(e.ft_balance_of(&users.bob), amount);
+    assert!(e.get_account_lockups(&users.alice).is_empty());
+    assert_eq!(e.get_lockup(0).total_balance, 0);
+
+    // A shorter period doesn't cut the time to claim of the finished lockups.
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+    let new_claim_deadline = e.get_lockup(1).claim_deadline;
+    assert_eq!(new_claim_deadline, Some(claim_deadline + 60 * ONE_DAY_SEC));
+    let set_claim_period = |duration| {
+        e.owner.function_call(
+            e.contract.contract.set_claim_period(Some(duration)),
+            DEFAULT_GAS,
+            1,
+        )
+    };
+    assert!(!set_claim_period(ONE_DAY_SEC).is_ok());
+    set_claim_period(30 * ONE_DAY_SEC).assert_success();
+    assert_eq!(e.get_lockup(1).claim_deadline, new_claim_deadline);
+}
+
+#[test]
+fn test_campaign() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let schedule = Schedule(vec![
+        Checkpoint {
+            timestamp: GENESIS_TIMESTAMP_SEC,
+            balance: 0,
+        },
+        Checkpoint {
+            timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+            balance: amount / 2,
+        },
+    ]);
+    // A tree with a single leaf, so the root is the leaf itself.
+    let merkle_root = e.hash_campaign_leaf(&users.alice, &schedule);
+    let expires_at = GENESIS_TIMESTAMP_SEC + 30 * ONE_DAY_SEC;
+    let owner_balance = e.ft_balance_of(&e.owner);
+    e.create_campaign(
+        &e.owner,
+        amount,
+        NewCampaign {
+            merkle_root,
+            expires_at,
+            category: Some("airdrop".to_string()),
+        },
+    )
+    .assert_success();
+    let campaign = e.get_campaign(0).unwrap();
+    assert_eq!(campaign.total_balance, amount);
+    assert_eq!(campaign.claimed_balance, 0);
+    assert!(e.get_account_lockups(&users.alice).is_empty());
+
+    // The leaf is bound to the account.
+    storage_deposit(
+        &users.bob,
+        FT_LOCKUP_ID,
+        &users.bob.account_id,
+        to_yocto("1"),
+    );
+    assert!(!e
+        .claim_campaign_lockup(&users.bob, 0, &schedule, vec![])
+        .is_ok());
+
+    storage_deposit(
+        &users.alice,
+        FT_LOCKUP_ID,
+        &users.alice.account_id,
+        to_yocto("1"),
+    );
+    let index: LockupIndex = e
+        .claim_campaign_lockup(&users.alice, 0, &schedule, vec![])
+        .unwrap_json();
+    let lockup = e.get_lockup(index);
+    assert_eq!(lockup.total_balance, amount / 2);
+    assert_eq!(lockup.creator_id, Some(e.owner.account_id.clone()));
+    assert_eq!(lockup.category, Some("airdrop".to_string()));
+    assert_eq!(e.get_campaign(0).unwrap().claimed_balance, amount / 2);
+    // Each leaf can only be claimed once.
+    assert!(!e
+        .claim_campaign_lockup(&users.alice, 0, &schedule, vec![])
+        .is_ok());
+
+    // The remaining balance returns to the creator after the expiration.
+    assert!(!e.refund_campaign(&e.owner, 0).is_ok());
+    e.set_time_sec(expires_at);
+    e.pause(PauseKind::Terminate).assert_success();
+    assert!(!e.refund_campaign(&users.charlie, 0).is_ok());
+    e.unpause(PauseKind::Terminate).assert_success();
+    let res: WrappedBalance = e.refund_campaign(&users.charlie, 0).unwrap_json();
+    assert_eq!(res.0, amount / 2);
+    assert_eq!(e.ft_balance_of(&e.owner), owner_balance - amount / 2);
+    assert!(e.get_campaign(0).unwrap().refunded);
+    assert!(!e.refund_campaign(&e.owner, 0).is_ok());
+}
+
+#[test]
+fn test_vouchers() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let keypair = voucher_keypair(1);
+    let owner_balance = e.ft_balance_of(&e.owner);
+    e.fund_voucher_pool(&e.owner, amount).assert_success();
+    assert_eq!(e.get_voucher_pool_balance(&e.owner), amount);
+    // Only the depositors can register the keys.
+    assert!(!e
+        .add_voucher_public_key(&users.alice, &voucher_public_key(&keypair))
+        .is_ok());
+    e.add_voucher_public_key(&e.owner, &voucher_public_key(&keypair))
+        .assert_success();
+
+    let voucher = Voucher {
+        depositor_id: e.owner.valid_account_id(),
+        account_id: users.alice.valid_account_id(),
+        token_account_id: e.token.valid_account_id(),
+        schedule: Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount / 2,
+            },
+        ]),
+        nonce: 1,
+        expires_at: GENESIS_TIMESTAMP_SEC + ONE_DAY_SEC,
+    };
+    // The voucher is bound to the beneficiary.
+    assert!(!e.redeem_voucher(&users.bob, &voucher, &keypair).is_ok());
+    // The key has to be registered by the depositor.
+    assert!(!e
+        .redeem_voucher(&users.alice, &voucher, &voucher_keypair(2))
+        .is_ok());
+    // The signature covers the whole voucher.
+    let mut forged_voucher = voucher.clone();
+    forged_voucher.nonce = 2;
+    let signature = keypair.sign(&voucher.message(FT_LOCKUP_ID));
+    assert!(!e
+        .redeem_signed_voucher(&users.alice, &forged_voucher, &keypair, &signature)
+        .is_ok());
+    // The signature is bound to the lockup contract.
+    let signature = keypair.sign(&voucher.message(LEGACY_FT_LOCKUP_ID));
+    assert!(!e
+        .redeem_signed_voucher(&users.alice, &voucher, &keypair, &signature)
+        .is_ok());
+    let signature = keypair.sign(&voucher.try_to_vec().unwrap());
+    assert!(!e
+        .redeem_signed_voucher(&users.alice, &voucher, &keypair, &signature)
+        .is_ok());
+
+    let index: LockupIndex = e
+        .redeem_voucher(&users.alice, &voucher, &keypair)
+        .unwrap_json();
+    let lockup = e.get_lockup(index);
+    assert_eq!(lockup.total_balance, amount / 2);
+    assert_eq!(lockup.creator_id, Some(e.owner.account_id.clone()));
+    assert_eq!(e.get_voucher_pool_balance(&e.owner), amount / 2);
+    // Each nonce can only be redeemed once.
+    assert!(!e.redeem_voucher(&users.alice, &voucher, &keypair).is_ok());
+
+    // The expired vouchers can't be redeemed.
+    let mut voucher = voucher;
+    voucher.nonce = 2;
+    e.set_time_sec(voucher.expires_at);
+    assert!(!e.redeem_voucher(&users.alice, &voucher, &keypair).is_ok());
+
+    // The remaining balance can be withdrawn by the depositor.
+    let res: WrappedBalance = e.withdraw_voucher_pool(&e.owner).unwrap_json();
+    assert_eq!(res.0, amount / 2);
+    assert_eq!(e.get_voucher_pool_balance(&e.owner), 0);
+    assert_eq!(e.ft_balance_of(&e.owner), owner_balance - amount / 2);
+}
+
+#[test]
+fn test_withdrawable_failed_claims() {
+    let e = Env::init(None);
+    let claimer = e.deploy_claimer();
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        claimer.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+
+    // The failed claim stays committed, and the balance becomes withdrawable.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+    ft_storage_deposit(&e.owner, TOKEN_ID, CLAIMER_ID);
+    e.concurrent_claims(&claimer, 1, Some(TOKEN_ID));
+    let lockups = e.get_account_lockups(&claimer);
+    assert_eq!(lockups[0].1.claimed_balance, amount / 2);
+    assert_eq!(lockups[0].1.unclaimed_balance, 0);
+    assert_eq!(e.get_withdrawable(&claimer), amount / 2);
+
+    // A failed withdrawal is credited back.
+    let res: WrappedBalance = e.withdraw(&claimer, None).unwrap_json();
+    assert_eq!(res.0, 0);
+    assert_eq!(e.get_withdrawable(&claimer), amount / 2);
+
+    // The next claim retries the transfer.
+    ft_storage_deposit(&e.owner, TOKEN_ID, CLAIMER_ID);
+    let res: WrappedBalance = e.claim(&claimer).unwrap_json();
+    assert_eq!(res.0, amount / 2);
+    assert_eq!(e.ft_balance_of(&claimer), amount / 2);
+    assert_eq!(e.get_withdrawable(&claimer), 0);
+
+    // The fully claimed lockup is removed, even if the transfer fails. The claimer is known to
+    // be registered, so the claim isn't checked, and its transfer fails once it unregisters.
+    // Note, this burns `amount / 2` tokens.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC);
+    e.concurrent_claims(&claimer, 0, Some(TOKEN_ID));
+    e.concurrent_claims(&claimer, 1, None);
+    assert!(e.get_account_lockups(&claimer).is_empty());
+    assert_eq!(e.get_withdrawable(&claimer), amount / 2);
+
+    ft_storage_deposit(&e.owner, TOKEN_ID, CLAIMER_ID);
+    assert!(!e.withdraw(&claimer, Some(amount)).is_ok());
+    let res: WrappedBalance = e.withdraw(&claimer, Some(amount / 4)).unwrap_json();
+    assert_eq!(res.0, amount / 4);
+    assert_eq!(e.ft_balance_of(&claimer), amount / 4);
+    assert_eq!(e.get_withdrawable(&claimer), amount / 4);
+}
+
+#[test]
+fn test_concurrent_claims() {
+    let e = Env::init(None);
+    let claimer = e.deploy_claimer();
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        claimer.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+
+    // The claimer unregisters from the token after the registration checks, so the first
+    // transfer fails, while the second claim is rejected by the lock.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+    ft_storage_deposit(&e.owner, TOKEN_ID, CLAIMER_ID);
+    e.concurrent_claims(&claimer, 2, Some(TOKEN_ID));
+    let lockups = e.get_account_lockups(&claimer);
+    assert_eq!(lockups[0].1.claimed_balance, amount / 2);
+    assert_eq!(e.get_withdrawable(&claimer), amount / 2);
+    assert_eq!(e.get_claim_lock(&claimer), None);
+
+    // The rollback restores the lockup without the second claim finalizing it.
+    e.set_rollback_failed_claims(true);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC);
+    ft_storage_deposit(&e.owner, TOKEN_ID, CLAIMER_ID);
+    e.concurrent_claims(&claimer, 2, Some(TOKEN_ID));
+    let lockups = e.get_account_lockups(&claimer);
+    assert_eq!(lockups.len(), 1);
+    assert_eq!(lockups[0].1.claimed_balance, amount / 2);
+    assert_eq!(lockups[0].1.unclaimed_balance, amount / 2);
+    assert_eq!(e.get_withdrawable(&claimer), amount / 2);
+    assert_eq!(e.get_claim_lock(&claimer), None);
+
+    // Only one of the concurrent claims transfers the balance.
+    ft_storage_deposit(&e.owner, TOKEN_ID, CLAIMER_ID);
+    e.concurrent_claims(&claimer, 2, None);
+    assert_eq!(e.ft_balance_of(&claimer), amount);
+    assert!(e.get_account_lockups(&claimer).is_empty());
+    assert_eq!(e.get_withdrawable(&claimer), 0);
+    assert_eq!(e.get_claim_lock(&claimer), None);
+}
+
+#[test]
+fn test_claim_registration_check() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+
+    // The claim stops early, without touching the lockup.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+    let res = e.claim(&users.alice);
+    let claimed: WrappedBalance = res.unwrap_json();
+    assert_eq!(claimed.0, 0);
+    let logs: Vec<String> = res
+        .promise_results()
+        .into_iter()
+        .flatten()
+        .flat_map(|result| result.logs().clone())
+        .collect();
+    assert!(logs
+        .iter()
+        .any(|log| log.contains("alice.near is not registered with token.near")));
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups[0].1.claimed_balance, 0);
+    assert_eq!(lockups[0].1.unclaimed_balance, amount / 2);
+    assert_eq!(e.get_withdrawable(&users.alice), 0);
+
+    // The attached deposit registers the account with the token first. Only the minimum
+    // storage balance is deposited, and the rest is refunded.
+    let initial_balance = users.alice.account().unwrap().amount;
+    let res: WrappedBalance = e
+        .claim_with_storage_deposit(&users.alice, to_yocto("1"))
+        .unwrap_json();
+    assert_eq!(res.0, amount / 2);
+    assert_eq!(e.ft_balance_of(&users.alice), amount / 2);
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups[0].1.claimed_balance, amount / 2);
+    assert!(users.alice.account().unwrap().amount > initial_balance - to_yocto("0.1"));
+
+    // Once registered, the whole deposit is refunded.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC);
+    let initial_balance = users.alice.account().unwrap().amount;
+    let res: WrappedBalance = e
+        .claim_with_storage_deposit(&users.alice, to_yocto("1"))
+        .unwrap_json();
+    assert_eq!(res.0, amount / 2);
+    assert_eq!(e.ft_balance_of(&users.alice), amount);
+    assert!(users.alice.account().unwrap().amount > initial_balance - to_yocto("0.1"));
+}
+
+#[test]
+fn test_claim_for() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let lockup = new_lockup(
+        users.alice.valid_account_id(),
+        Schedule(vec![
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC,
+                balance: 0,
+            },
+            Checkpoint {
+                timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                balance: amount,
+            },
+        ]),
+    );
+    let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+    assert_eq!(balance.0, amount);
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+
+    // Only keepers, unless the beneficiary allows anyone.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 4);
+    assert!(!e.claim_for(&users.bob, &users.alice, None).is_ok());
+
+    // The keeper fee is capped.
+    e.add_keeper(&users.charlie);
+    assert!(!e.set_max_keeper_fee_bps(10_000).is_ok());
+    e.set_max_keeper_fee_bps(100).assert_success();
+    assert!(!e.claim_for(&users.charlie, &users.alice, Some(200)).is_ok());
+    let res: WrappedBalance = e
+        .claim_for(&users.charlie, &users.alice, Some(100))
+        .unwrap_json();
+    let fee = amount / 4 / 100;
+    assert_eq!(res.0, amount / 4 - fee);
+    assert_eq!(e.ft_balance_of(&users.alice), amount / 4 - fee);
+    assert_eq!(e.get_withdrawable(&users.charlie), fee);
+    let lockups = e.get_account_lockups(&users.alice);
+    assert_eq!(lockups[0].1.claimed_balance, amount / 4);
+
+    // Anyone can claim without a fee, once the beneficiary allows it.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+    e.set_claim_for_allowed(&users.alice, true).assert_success();
+    assert!(!e.claim_for(&users.bob, &users.alice, Some(100)).is_ok());
+    let res: WrappedBalance = e.claim_for(&users.bob, &users.alice, None).unwrap_json();
+    assert_eq!(res.0, amount / 4);
+    assert_eq!(e.ft_balance_of(&users.alice), amount / 2 - fee);
+    assert_eq!(e.ft_balance_of(&users.bob), 0);
+
+    // Blacklisted accounts can't be claimed for.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC * 3 / 4);
+    e.add_to_blacklist(&users.alice, None, false)
+        .assert_success();
+    assert!(!e.claim_for(&users.charlie, &users.alice, None).is_ok());
+    assert_eq!(e.ft_balance_of(&users.alice), amount / 2 - fee);
+}
+
+#[test]
+fn test_claim_batch() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    for user in &[&users.alice, &users.bob, &users.dude, &users.eve] {
+        let lockup = new_lockup(
+            user.valid_account_id(),
+            Schedule(vec![
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC,
+                    balance: 0,
+                },
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                    balance: amount,
+                },
+            ]),
+        );
+        let balance: WrappedBalance = e.add_lockup(&e.owner, amount, &lockup).unwrap_json();
+        assert_eq!(balance.0, amount);
+    }
+    ft_storage_deposit(&users.alice, TOKEN_ID, &users.alice.account_id);
+    ft_storage_deposit(&users.bob, TOKEN_ID, &users.bob.account_id);
+    e.add_to_blacklist(&users.eve, None, false).assert_success();
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+
+    // Only keepers.
+    assert!(!e
+        .claim_batch(&users.charlie, &[&users.alice], MAX_GAS)
+        .is_ok());
+    e.add_keeper(&users.charlie);
+
+    // The accounts, that aren't known to be registered with the token, are checked first, and
+    // an unregistered account claims nothing.
+    let res: BatchClaimResult = e
+        .claim_batch(
+            &users.charlie,
+            &[&users.alice, &users.dude, &users.eve],
+            MAX_GAS,
+        )
+        .unwrap_json();
+    assert_eq!(res.num_processed, 3);
+    assert!(res.claimed_account_ids.is_empty());
+    assert_eq!(
+        res.checked_account_ids,
+        vec![
+            users.alice.account_id.clone(),
+            users.dude.account_id.clone()
+        ]
+    );
+    assert_eq!(res.skipped_account_ids, vec![users.eve.account_id.clone()]);
+    assert_eq!(e.ft_balance_of(&users.alice), amount / 2);
+    assert_eq!(e.ft_balance_of(&users.dude), 0);
+    assert_eq!(e.get_withdrawable(&users.dude), 0);
+    let lockups = e.get_account_lockups(&users.dude);
+    assert_eq!(lockups[0].1.unclaimed_balance, amount / 2);
+    let lockups = e.get_account_lockups(&users.eve);
+    assert_eq!(lockups[0].1.unclaimed_balance, amount / 2);
+
+    // The batch stops once the gas runs out. Alice is known to be registered, and Bob needs
+    // more gas to be checked.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC * 3 / 4);
+    let res: BatchClaimResult = e
+        .claim_batch(&users.charlie, &[&users.alice, &users.bob], 100 * T_GAS)
+        .unwrap_json();
+    assert_eq!(res.num_processed, 1);
+    assert_eq!(
+        res.claimed_account_ids,
+        vec![users.alice.account_id.clone()]
+    );
+    assert_eq!(e.ft_balance_of(&users.alice), amount * 3 / 4);
+    assert_eq!(e.ft_balance_of(&users.bob), 0);
+
+    // A failed transfer doesn't affect the other accounts.
+    storage_force_unregister(&users.alice, TOKEN_ID);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC);
+    let res: BatchClaimResult = e
+        .claim_batch(&users.charlie, &[&users.alice, &users.bob], MAX_GAS)
+        .unwrap_json();
+    assert_eq!(res.num_processed, 2);
+    assert_eq!(
+        res.claimed_account_ids,
+        vec![users.alice.account_id.clone()]
+    );
+    assert_eq!(res.checked_account_ids, vec![users.bob.account_id.clone()]);
+    assert_eq!(e.get_withdrawable(&users.alice), amount / 4);
+    assert_eq!(e.ft_balance_of(&users.bob), amount);
+}
+
+#[test]
+fn test_claim_batch_size() {
+    let e = Env::init(None);
+    let users = Users::init(&e);
+    let amount = d(60000, TOKEN_DECIMALS);
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC);
+    let accounts: Vec<_> = (0..6)
+        .map(|i| {
+            e.near
+                .create_user(format!("user{}.near", i), to_yocto("10"))
+        })
+        .collect();
+    let accounts: Vec<_> = accounts.iter().collect();
+    for user in &accounts {
+        let lockup = new_lockup(
+            user.valid_account_id(),
+            Schedule(vec![
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC,
+                    balance: 0,
+                },
+                Checkpoint {
+                    timestamp: GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC,
+                    balance: amount,
+                },
+            ]),
+        );
+        e.add_lockup(&e.owner, amount, &lockup).assert_success();
+        ft_storage_deposit(user, TOKEN_ID, &user.account_id);
+    }
+    e.add_keeper(&users.charlie);
+
+    // With 300 TGas, a batch checks 3 accounts with a single lockup.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC / 2);
+    for from in [0, 3] {
+        let res: BatchClaimResult = e
+            .claim_batch(&users.charlie, &accounts[from..], MAX_GAS)
+            .unwrap_json();
+        assert_eq!(res.num_processed, 3);
+        assert_eq!(res.checked_account_ids.len(), 3);
+    }
+    for user in &accounts {
+        assert_eq!(e.ft_balance_of(user), amount / 2);
+    }
+
+    // Once they are known to be registered, it claims for 5 of them.
+    e.set_time_sec(GENESIS_TIMESTAMP_SEC + ONE_YEAR_SEC);
+    let res: BatchClaimResult = e
+        .claim_batch(&users.charlie, &accounts, MAX_GAS)
+        .unwrap_json();
+    assert_eq!(res.num_processed, 5);
+    assert_eq!(res.claimed_account_ids.len(), 5);
+    for user in &accounts[..5] {
+        assert_eq!(e.ft_balance_of(user), amount);
+    }
+    assert_eq!(e.ft_balance_of(accounts[5]), amount / 2);
+}
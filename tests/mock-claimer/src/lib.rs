@@ -0,0 +1,70 @@
+//! A mock beneficiary contract for the lockup sim tests.
+//! It submits several claims at once. The claims are independent receipts, so all of them run
+//! before the transfers of the first claim are resolved. It can also unregister from the token
+//! after the claims have checked the registration, but before their transfers.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen, AccountId, Gas, Promise};
+
+near_sdk::setup_alloc!();
+
+const GAS_FOR_CLAIM: Gas = 100_000_000_000_000;
+const GAS_FOR_STORAGE_BALANCE_OF: Gas = 5_000_000_000_000;
+const GAS_FOR_UNREGISTER: Gas = 30_000_000_000_000;
+const GAS_FOR_STORAGE_UNREGISTER: Gas = 20_000_000_000_000;
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct Contract {}
+
+#[near_bindgen]
+impl Contract {
+    pub fn claim(
+        &mut self,
+        lockup_contract_id: ValidAccountId,
+        num_claims: u32,
+        unregister_token_id: Option<ValidAccountId>,
+    ) {
+        for _ in 0..num_claims {
+            Promise::new(lockup_contract_id.clone().into()).function_call(
+                b"claim".to_vec(),
+                b"{}".to_vec(),
+                0,
+                GAS_FOR_CLAIM,
+            );
+        }
+        if let Some(token_account_id) = unregister_token_id {
+            // Delays the unregistration by two blocks, to the block of the claim callbacks.
+            Promise::new(token_account_id.clone().into())
+                .function_call(
+                    b"storage_balance_of".to_vec(),
+                    json!({ "account_id": env::current_account_id() })
+                        .to_string()
+                        .into_bytes(),
+                    0,
+                    GAS_FOR_STORAGE_BALANCE_OF,
+                )
+                .then(
+                    Promise::new(env::current_account_id()).function_call(
+                        b"unregister".to_vec(),
+                        json!({ "token_account_id": token_account_id })
+                            .to_string()
+                            .into_bytes(),
+                        0,
+                        GAS_FOR_UNREGISTER,
+                    ),
+                );
+        }
+    }
+
+    #[private]
+    pub fn unregister(&mut self, token_account_id: AccountId) -> Promise {
+        Promise::new(token_account_id).function_call(
+            b"storage_unregister".to_vec(),
+            json!({ "force": true }).to_string().into_bytes(),
+            1,
+            GAS_FOR_STORAGE_UNREGISTER,
+        )
+    }
+}
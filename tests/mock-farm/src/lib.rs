@@ -0,0 +1,101 @@
+//! A mock staking contract for the lockup sim tests.
+//! It holds the staked principal per depositor and account, and pays a fixed reward share of
+//! the unstaked amount to the account from its own token balance.
+use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{ValidAccountId, WrappedBalance, U128};
+use near_sdk::serde::Deserialize;
+use near_sdk::{
+    env, near_bindgen, serde_json, AccountId, Balance, Gas, PanicOnDefault, Promise, PromiseOrValue,
+};
+
+near_sdk::setup_alloc!();
+
+const GAS_FOR_FT_TRANSFER: Gas = 15_000_000_000_000;
+const ONE_YOCTO: Balance = 1;
+const MAX_REWARD_BPS: u32 = 10_000;
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct StakeMessage {
+    account_id: AccountId,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Contract {
+    token_account_id: AccountId,
+    /// The reward paid on unstake, in basis points of the unstaked amount.
+    reward_bps: u32,
+    /// The staked principal by the depositor and the account.
+    stakes: LookupMap<(AccountId, AccountId), Balance>,
+}
+
+#[near_bindgen]
+impl Contract {
+    #[init]
+    pub fn new(token_account_id: ValidAccountId, reward_bps: u32) -> Self {
+        assert!(reward_bps <= MAX_REWARD_BPS);
+        Self {
+            token_account_id: token_account_id.into(),
+            reward_bps,
+            stakes: LookupMap::new(b"s".to_vec()),
+        }
+    }
+
+    pub fn unstake(&mut self, account_id: ValidAccountId, amount: WrappedBalance) -> Promise {
+        let key = (env::predecessor_account_id(), account_id.clone().into());
+        let staked_balance = self.stakes.get(&key).unwrap_or(0);
+        assert!(amount.0 <= staked_balance, "Not enough staked balance");
+        self.stakes.insert(&key, &(staked_balance - amount.0));
+        let reward = amount.0 * self.reward_bps as Balance / MAX_REWARD_BPS as Balance;
+        if reward > 0 {
+            ext_fungible_token::ft_transfer(
+                account_id.into(),
+                reward.into(),
+                Some("Staking reward".to_string()),
+                &self.token_account_id,
+                ONE_YOCTO,
+                GAS_FOR_FT_TRANSFER,
+            );
+        }
+        ext_fungible_token::ft_transfer(
+            env::predecessor_account_id(),
+            amount,
+            Some("Unstake".to_string()),
+            &self.token_account_id,
+            ONE_YOCTO,
+            GAS_FOR_FT_TRANSFER,
+        )
+    }
+
+    pub fn get_stake(&self, depositor_id: ValidAccountId, account_id: ValidAccountId) -> U128 {
+        self.stakes
+            .get(&(depositor_id.into(), account_id.into()))
+            .unwrap_or(0)
+            .into()
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Contract {
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: ValidAccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.token_account_id,
+            "Invalid token ID"
+        );
+        let message: StakeMessage = serde_json::from_str(&msg).expect("Expected StakeMessage");
+        let key = (sender_id.into(), message.account_id);
+        let staked_balance = self.stakes.get(&key).unwrap_or(0);
+        self.stakes.insert(&key, &(staked_balance + amount.0));
+        PromiseOrValue::Value(0.into())
+    }
+}
@@ -1,29 +1,50 @@
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer};
 use near_contract_standards::fungible_token::metadata::{FungibleTokenMetadata, FT_METADATA_SPEC};
-use near_sdk::json_types::{ValidAccountId, WrappedBalance};
+use near_sdk::json_types::{Base58CryptoHash, Base58PublicKey, ValidAccountId, WrappedBalance};
 use near_sdk::serde_json::json;
-use near_sdk::{env, serde_json, Balance, Gas, Timestamp};
+use near_sdk::{env, serde_json, AccountId, Balance, Gas, Timestamp};
 use near_sdk_sim::runtime::GenesisConfig;
 use near_sdk_sim::{
     deploy, init_simulator, to_yocto, ContractAccount, ExecutionResult, UserAccount,
 };
+use std::collections::HashMap;
+use std::convert::TryFrom;
 
+pub use ft_lockup::campaign::{CampaignIndex, CampaignView, NewCampaign};
+pub use ft_lockup::farm::LockupStake;
+pub use ft_lockup::keeper::BatchClaimResult;
 pub use ft_lockup::lockup::{Lockup, LockupIndex};
-use ft_lockup::view::LockupView;
-pub use ft_lockup::{ContractContract as FtLockupContract, TimestampSec};
+pub use ft_lockup::notification::DepositMessage;
+pub use ft_lockup::pause::{PauseKind, PauseState};
+pub use ft_lockup::schedule::Schedule;
+pub use ft_lockup::termination::TerminationConfig;
+pub use ft_lockup::view::{LockupFilter, LockupStatus, LockupView, LockupsPage};
+pub use ft_lockup::voting_power::{VotingPowerConfig, VotingPowerPage, VotingPowerWeighting};
+pub use ft_lockup::voucher::Voucher;
+pub use ft_lockup::{ContractContract as FtLockupContract, TimestampSec, NEAR_TOKEN_ID};
 
 near_sdk_sim::lazy_static_include::lazy_static_include_bytes! {
     FT_LOCKUP_WASM_BYTES => "res/ft_lockup.wasm",
+    LEGACY_FT_LOCKUP_WASM_BYTES => "res/ft_lockup_legacy.wasm",
     FUNGIBLE_TOKEN_WASM_BYTES => "res/fungible_token.wasm",
+    MOCK_FARM_WASM_BYTES => "res/mock_farm.wasm",
+    MOCK_CLAIMER_WASM_BYTES => "res/mock_claimer.wasm",
 }
 
 pub const NEAR: &str = "near";
 pub const TOKEN_ID: &str = "token.near";
+pub const TOKEN2_ID: &str = "token2.near";
 pub const FT_LOCKUP_ID: &str = "ft-lockup.near";
 pub const OWNER_ID: &str = "owner.near";
+pub const LEGACY_FT_LOCKUP_ID: &str = "legacy-lockup.near";
+pub const FARM_ID: &str = "farm.near";
+pub const CLAIMER_ID: &str = "claimer.near";
 
 pub const T_GAS: Gas = 10u64.pow(12);
 pub const DEFAULT_GAS: Gas = 15 * T_GAS;
 pub const MAX_GAS: Gas = 300 * T_GAS;
+/// The initial balance of the lockup contract, that covers the storage of the contract code.
+pub const LOCKUP_CONTRACT_BALANCE: Balance = 10 * 10u128.pow(24);
 pub const CLAIM_GAS: Gas = 100 * T_GAS;
 
 pub const TOKEN_DECIMALS: u8 = 18;
@@ -45,6 +66,17 @@ pub struct Users {
     pub eve: UserAccount,
 }
 
+/// Returns the deterministic voucher signing key of the seed.
+pub fn voucher_keypair(seed: u8) -> Keypair {
+    let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+pub fn voucher_public_key(keypair: &Keypair) -> Base58PublicKey {
+    Base58PublicKey::try_from([vec![0], keypair.public.to_bytes().to_vec()].concat()).unwrap()
+}
+
 pub fn storage_deposit(
     user: &UserAccount,
     contract_id: &str,
@@ -81,6 +113,105 @@ pub fn ft_storage_deposit(user: &UserAccount, token_account_id: &str, account_id
     );
 }
 
+pub fn deploy_token(near: &UserAccount, owner: &UserAccount, token_id: &str) -> UserAccount {
+    near.deploy_and_init(
+        &FUNGIBLE_TOKEN_WASM_BYTES,
+        token_id.to_string(),
+        "new",
+        &json!({
+            "owner_id": owner.valid_account_id(),
+            "total_supply": WrappedBalance::from(TOKEN_TOTAL_SUPPLY),
+            "metadata": FungibleTokenMetadata {
+                spec: FT_METADATA_SPEC.to_string(),
+                name: "Token".to_string(),
+                symbol: "TOKEN".to_string(),
+                icon: None,
+                reference: None,
+                reference_hash: None,
+                decimals: TOKEN_DECIMALS,
+            }
+        })
+        .to_string()
+        .into_bytes(),
+        to_yocto("10"),
+        DEFAULT_GAS,
+    )
+}
+
+/// Deploys the first released version of the lockup contract, with the owner whitelisted.
+pub fn deploy_legacy_lockup(
+    near: &UserAccount,
+    owner: &UserAccount,
+    contract_id: &str,
+) -> UserAccount {
+    let contract = near.deploy_and_init(
+        &LEGACY_FT_LOCKUP_WASM_BYTES,
+        contract_id.to_string(),
+        "new",
+        &json!({
+            "token_account_id": TOKEN_ID,
+            "deposit_whitelist": vec![owner.valid_account_id()],
+        })
+        .to_string()
+        .into_bytes(),
+        LOCKUP_CONTRACT_BALANCE,
+        DEFAULT_GAS,
+    );
+    ft_storage_deposit(owner, TOKEN_ID, contract_id);
+    contract
+}
+
+pub fn ft_transfer_call(
+    user: &UserAccount,
+    token_id: &str,
+    receiver_id: &str,
+    amount: Balance,
+    msg: &str,
+) -> ExecutionResult {
+    user.call(
+        token_id.to_string(),
+        "ft_transfer_call",
+        &json!({
+            "receiver_id": receiver_id,
+            "amount": WrappedBalance::from(amount),
+            "msg": msg,
+        })
+        .to_string()
+        .into_bytes(),
+        MAX_GAS,
+        1,
+    )
+}
+
+/// Returns the gas burnt by the transaction, including all its receipts.
+/// The receipts are taken from the runtime, so it's only valid for the last transaction.
+pub fn total_gas_burnt(res: &ExecutionResult) -> Gas {
+    res.promise_results()
+        .iter()
+        .flatten()
+        .map(|outcome| outcome.gas_burnt())
+        .sum()
+}
+
+/// Returns a lockup of the token with the given schedule, without the optional settings.
+pub fn new_lockup(account_id: ValidAccountId, schedule: Schedule) -> Lockup {
+    Lockup {
+        account_id,
+        token_account_id: TOKEN_ID.to_string(),
+        schedule,
+        schedule_id: None,
+        claimed_balance: 0,
+        termination_config: None,
+        creator_id: None,
+        created_at: None,
+        memo: None,
+        category: None,
+        cancellation_deadline: None,
+        claim_deadline: None,
+        num_applied_freezes: 0,
+    }
+}
+
 pub fn to_nano(timestamp: u32) -> Timestamp {
     Timestamp::from(timestamp) * 10u64.pow(9)
 }
@@ -93,35 +224,14 @@ impl Env {
         let near = root.create_user(NEAR.to_string(), to_yocto("1000000"));
         let owner = near.create_user(OWNER_ID.to_string(), to_yocto("10000"));
 
-        let token = near.deploy_and_init(
-            &FUNGIBLE_TOKEN_WASM_BYTES,
-            TOKEN_ID.to_string(),
-            "new",
-            &json!({
-                "owner_id": owner.valid_account_id(),
-                "total_supply": WrappedBalance::from(TOKEN_TOTAL_SUPPLY),
-                "metadata": FungibleTokenMetadata {
-                    spec: FT_METADATA_SPEC.to_string(),
-                    name: "Token".to_string(),
-                    symbol: "TOKEN".to_string(),
-                    icon: None,
-                    reference: None,
-                    reference_hash: None,
-                    decimals: TOKEN_DECIMALS,
-                }
-            })
-            .to_string()
-            .into_bytes(),
-            to_yocto("10"),
-            DEFAULT_GAS,
-        );
+        let token = deploy_token(&near, &owner, TOKEN_ID);
 
         let contract = deploy!(
             contract: FtLockupContract,
             contract_id: FT_LOCKUP_ID.to_string(),
             bytes: &FT_LOCKUP_WASM_BYTES,
             signer_account: near,
-            deposit: to_yocto("10"),
+            deposit: LOCKUP_CONTRACT_BALANCE,
             gas: DEFAULT_GAS,
             init_method: new(
                 token.valid_account_id(),
@@ -129,7 +239,19 @@ impl Env {
             )
         );
 
+        // The contract is deployed by `near`, so the ownership is handed over to the owner.
+        near.function_call(
+            contract.contract.propose_owner(owner.valid_account_id()),
+            DEFAULT_GAS,
+            1,
+        )
+        .assert_success();
+        owner
+            .function_call(contract.contract.accept_ownership(), DEFAULT_GAS, 1)
+            .assert_success();
+
         ft_storage_deposit(&owner, TOKEN_ID, FT_LOCKUP_ID);
+        storage_deposit(&owner, FT_LOCKUP_ID, OWNER_ID, to_yocto("1"));
 
         Self {
             root,
@@ -140,27 +262,211 @@ impl Env {
         }
     }
 
-    pub fn ft_transfer_call(
+    /// Deploys the first released version of the contract, that `upgrade` migrates from.
+    pub fn init_legacy() -> Self {
+        let mut genesis_config = GenesisConfig::default();
+        genesis_config.block_prod_time = 0;
+        let root = init_simulator(Some(genesis_config));
+        let near = root.create_user(NEAR.to_string(), to_yocto("1000000"));
+        let owner = near.create_user(OWNER_ID.to_string(), to_yocto("10000"));
+
+        let token = deploy_token(&near, &owner, TOKEN_ID);
+
+        let contract = ContractAccount {
+            user_account: deploy_legacy_lockup(&near, &owner, FT_LOCKUP_ID),
+            contract: FtLockupContract {
+                account_id: FT_LOCKUP_ID.to_string(),
+            },
+        };
+
+        Self {
+            root,
+            near,
+            owner,
+            contract,
+            token,
+        }
+    }
+
+    /// Deploys the current version of the contract over the legacy one and migrates the state.
+    pub fn upgrade(&self) -> ExecutionResult {
+        self.contract
+            .user_account
+            .create_transaction(FT_LOCKUP_ID.to_string())
+            .deploy_contract(FT_LOCKUP_WASM_BYTES.to_vec())
+            .function_call("migrate".to_string(), b"{}".to_vec(), MAX_GAS, 0)
+            .submit()
+    }
+
+    pub fn migrate_lockups(&self, user: &UserAccount, limit: u32) -> u32 {
+        user.function_call(self.contract.contract.migrate_lockups(limit), MAX_GAS, 0)
+            .unwrap_json()
+    }
+
+    pub fn get_num_legacy_lockups(&self) -> u32 {
+        self.near
+            .view_method_call(self.contract.contract.get_num_legacy_lockups())
+            .unwrap_json()
+    }
+
+    pub fn deploy_token(&self, token_id: &str) -> UserAccount {
+        deploy_token(&self.near, &self.owner, token_id)
+    }
+
+    /// Deploys the mock farm, that pays `reward_bps` of the unstaked amount as a reward.
+    pub fn deploy_farm(&self, reward_bps: u32) -> UserAccount {
+        let farm = self.near.deploy_and_init(
+            &MOCK_FARM_WASM_BYTES,
+            FARM_ID.to_string(),
+            "new",
+            &json!({
+                "token_account_id": TOKEN_ID,
+                "reward_bps": reward_bps,
+            })
+            .to_string()
+            .into_bytes(),
+            to_yocto("10"),
+            DEFAULT_GAS,
+        );
+        ft_storage_deposit(&self.owner, TOKEN_ID, FARM_ID);
+        self.owner
+            .function_call(
+                self.contract
+                    .contract
+                    .add_to_farm_whitelist(farm.valid_account_id()),
+                DEFAULT_GAS,
+                1,
+            )
+            .assert_success();
+        farm
+    }
+
+    /// Deploys the mock beneficiary contract, that submits several claims at once.
+    pub fn deploy_claimer(&self) -> UserAccount {
+        self.near.deploy(
+            &MOCK_CLAIMER_WASM_BYTES,
+            CLAIMER_ID.to_string(),
+            to_yocto("10"),
+        )
+    }
+
+    /// Submits `num_claims` concurrent claims of the claimer's lockups. If `unregister_token_id`
+    /// is given, the claimer unregisters from the token after the claims have checked the
+    /// registration, so their transfers fail.
+    pub fn concurrent_claims(
         &self,
-        user: &UserAccount,
-        amount: Balance,
-        msg: &str,
-    ) -> ExecutionResult {
+        claimer: &UserAccount,
+        num_claims: u32,
+        unregister_token_id: Option<&str>,
+    ) {
+        self.owner
+            .call(
+                claimer.account_id.clone(),
+                "claim",
+                &json!({
+                    "lockup_contract_id": FT_LOCKUP_ID,
+                    "num_claims": num_claims,
+                    "unregister_token_id": unregister_token_id,
+                })
+                .to_string()
+                .into_bytes(),
+                MAX_GAS,
+                0,
+            )
+            .assert_success();
+    }
+
+    pub fn get_claim_lock(&self, user: &UserAccount) -> Option<TimestampSec> {
+        self.near
+            .view_method_call(
+                self.contract
+                    .contract
+                    .get_claim_lock(user.valid_account_id()),
+            )
+            .unwrap_json()
+    }
+
+    pub fn ft_transfer(&self, user: &UserAccount, receiver_id: &str, amount: Balance) {
         user.call(
             self.token.account_id.clone(),
-            "ft_transfer_call",
+            "ft_transfer",
             &json!({
-                "receiver_id": self.contract.user_account.valid_account_id(),
+                "receiver_id": receiver_id,
                 "amount": WrappedBalance::from(amount),
-                "msg": msg,
             })
             .to_string()
             .into_bytes(),
+            DEFAULT_GAS,
+            1,
+        )
+        .assert_success();
+    }
+
+    pub fn stake(
+        &self,
+        user: &UserAccount,
+        lockup_index: LockupIndex,
+        amount: Option<Balance>,
+    ) -> ExecutionResult {
+        user.function_call(
+            self.contract.contract.stake(
+                lockup_index,
+                ValidAccountId::try_from(FARM_ID).unwrap(),
+                amount.map(|a| a.into()),
+            ),
             MAX_GAS,
             1,
         )
     }
 
+    pub fn unstake(
+        &self,
+        user: &UserAccount,
+        lockup_index: LockupIndex,
+        amount: Option<Balance>,
+    ) -> ExecutionResult {
+        user.function_call(
+            self.contract
+                .contract
+                .unstake(lockup_index, amount.map(|a| a.into())),
+            MAX_GAS,
+            1,
+        )
+    }
+
+    pub fn terminate(&self, user: &UserAccount, lockup_index: LockupIndex) -> ExecutionResult {
+        user.function_call(
+            self.contract.contract.terminate(lockup_index, None),
+            MAX_GAS,
+            0,
+        )
+    }
+
+    pub fn get_lockup_stake(&self, lockup_index: LockupIndex) -> Option<LockupStake> {
+        self.near
+            .view_method_call(self.contract.contract.get_lockup_stake(lockup_index))
+            .unwrap_json()
+    }
+
+    pub fn ft_transfer_call(
+        &self,
+        user: &UserAccount,
+        amount: Balance,
+        msg: &str,
+    ) -> ExecutionResult {
+        self.ft_transfer_call_token(user, &self.token.account_id, amount, msg)
+    }
+
+    pub fn ft_transfer_call_token(
+        &self,
+        user: &UserAccount,
+        token_id: &str,
+        amount: Balance,
+        msg: &str,
+    ) -> ExecutionResult {
+        ft_transfer_call(user, token_id, FT_LOCKUP_ID, amount, msg)
+    }
+
     pub fn add_lockup(
         &self,
         user: &UserAccount,
@@ -170,17 +476,411 @@ impl Env {
         self.ft_transfer_call(user, amount, &serde_json::to_string(lockup).unwrap())
     }
 
-    pub fn claim(&self, user: &UserAccount) -> ExecutionResult {
-        user.function_call(self.contract.contract.claim(), CLAIM_GAS, 0)
+    pub fn add_lockup_with_notify(
+        &self,
+        user: &UserAccount,
+        amount: Balance,
+        lockup: &Lockup,
+        notify_msg: &str,
+    ) -> ExecutionResult {
+        let message = DepositMessage {
+            lockup: lockup.clone(),
+            notify: true,
+            notify_msg: Some(notify_msg.to_string()),
+        };
+        self.ft_transfer_call(user, amount, &serde_json::to_string(&message).unwrap())
     }
 
-    pub fn get_account_lockups(&self, user: &UserAccount) -> Vec<(LockupIndex, LockupView)> {
+    pub fn add_lockup_with_token(
+        &self,
+        user: &UserAccount,
+        token_id: &str,
+        amount: Balance,
+        lockup: &Lockup,
+    ) -> ExecutionResult {
+        self.ft_transfer_call_token(
+            user,
+            token_id,
+            amount,
+            &serde_json::to_string(lockup).unwrap(),
+        )
+    }
+
+    pub fn create_near_lockup(
+        &self,
+        user: &UserAccount,
+        amount: Balance,
+        lockup: &Lockup,
+    ) -> ExecutionResult {
+        user.function_call(
+            self.contract
+                .contract
+                .create_near_lockup(lockup.clone(), None, None),
+            DEFAULT_GAS,
+            amount,
+        )
+    }
+
+    pub fn add_to_token_whitelist(&self, user: &UserAccount, token_id: &str) -> ExecutionResult {
+        user.function_call(
+            self.contract
+                .contract
+                .add_to_token_whitelist(ValidAccountId::try_from(token_id).unwrap()),
+            DEFAULT_GAS,
+            1,
+        )
+    }
+
+    pub fn set_voting_power_config(&self, weighting: VotingPowerWeighting) -> ExecutionResult {
+        self.owner.function_call(
+            self.contract
+                .contract
+                .set_voting_power_config(VotingPowerConfig {
+                    token_account_id: TOKEN_ID.to_string(),
+                    weighting,
+                }),
+            DEFAULT_GAS,
+            1,
+        )
+    }
+
+    pub fn get_voting_power(
+        &self,
+        user: &UserAccount,
+        at_timestamp: Option<TimestampSec>,
+    ) -> Balance {
+        let voting_power: WrappedBalance = self
+            .near
+            .view_method_call(
+                self.contract
+                    .contract
+                    .get_voting_power(user.valid_account_id(), at_timestamp),
+            )
+            .unwrap_json();
+        voting_power.0
+    }
+
+    pub fn get_total_voting_power_page(
+        &self,
+        at_timestamp: Option<TimestampSec>,
+        from_index: Option<LockupIndex>,
+        limit: Option<LockupIndex>,
+    ) -> VotingPowerPage {
         self.near
+            .view_method_call(self.contract.contract.get_total_voting_power(
+                at_timestamp,
+                from_index,
+                limit,
+            ))
+            .unwrap_json()
+    }
+
+    pub fn get_total_voting_power(&self, at_timestamp: Option<TimestampSec>) -> Balance {
+        let mut voting_power = 0;
+        let mut from_index = Some(0);
+        while let Some(index) = from_index {
+            let page = self.get_total_voting_power_page(at_timestamp, Some(index), None);
+            voting_power += page.voting_power.0;
+            from_index = page.next_index;
+        }
+        voting_power
+    }
+
+    pub fn get_locked_balance_at(&self, user: &UserAccount, timestamp: TimestampSec) -> Balance {
+        let balance: WrappedBalance = self
+            .near
+            .view_method_call(self.contract.contract.get_locked_balance_at(
+                user.valid_account_id(),
+                timestamp,
+                self.token.account_id(),
+            ))
+            .unwrap_json();
+        balance.0
+    }
+
+    pub fn cancel_lockup(&self, user: &UserAccount, lockup_index: LockupIndex) -> ExecutionResult {
+        user.function_call(
+            self.contract.contract.cancel_lockup(lockup_index),
+            CLAIM_GAS,
+            0,
+        )
+    }
+
+    pub fn claw_back(&self, user: &UserAccount, lockup_index: LockupIndex) -> ExecutionResult {
+        user.function_call(self.contract.contract.claw_back(lockup_index), CLAIM_GAS, 0)
+    }
+
+    pub fn create_campaign(
+        &self,
+        user: &UserAccount,
+        amount: Balance,
+        campaign: NewCampaign,
+    ) -> ExecutionResult {
+        self.ft_transfer_call(user, amount, &json!({ "campaign": campaign }).to_string())
+    }
+
+    pub fn claim_campaign_lockup(
+        &self,
+        user: &UserAccount,
+        campaign_index: CampaignIndex,
+        schedule: &Schedule,
+        proof: Vec<Base58CryptoHash>,
+    ) -> ExecutionResult {
+        user.function_call(
+            self.contract
+                .contract
+                .claim_campaign_lockup(campaign_index, schedule.clone(), proof),
+            DEFAULT_GAS,
+            0,
+        )
+    }
+
+    pub fn refund_campaign(
+        &self,
+        user: &UserAccount,
+        campaign_index: CampaignIndex,
+    ) -> ExecutionResult {
+        user.function_call(
+            self.contract.contract.refund_campaign(campaign_index),
+            CLAIM_GAS,
+            0,
+        )
+    }
+
+    pub fn hash_campaign_leaf(&self, user: &UserAccount, schedule: &Schedule) -> Base58CryptoHash {
+        self.near
+            .view_method_call(
+                self.contract
+                    .contract
+                    .hash_campaign_leaf(user.valid_account_id(), schedule.clone()),
+            )
+            .unwrap_json()
+    }
+
+    pub fn fund_voucher_pool(&self, user: &UserAccount, amount: Balance) -> ExecutionResult {
+        self.ft_transfer_call(user, amount, &json!({ "voucher_pool": {} }).to_string())
+    }
+
+    pub fn add_voucher_public_key(
+        &self,
+        user: &UserAccount,
+        public_key: &Base58PublicKey,
+    ) -> ExecutionResult {
+        user.function_call(
+            self.contract
+                .contract
+                .add_voucher_public_key(public_key.clone()),
+            DEFAULT_GAS,
+            1,
+        )
+    }
+
+    pub fn redeem_voucher(
+        &self,
+        user: &UserAccount,
+        voucher: &Voucher,
+        keypair: &Keypair,
+    ) -> ExecutionResult {
+        let signature = keypair.sign(&voucher.message(FT_LOCKUP_ID));
+        self.redeem_signed_voucher(user, voucher, keypair, &signature)
+    }
+
+    pub fn redeem_signed_voucher(
+        &self,
+        user: &UserAccount,
+        voucher: &Voucher,
+        keypair: &Keypair,
+        signature: &Signature,
+    ) -> ExecutionResult {
+        user.function_call(
+            self.contract.contract.redeem_voucher(
+                voucher.clone(),
+                voucher_public_key(keypair),
+                signature.to_bytes().to_vec().into(),
+            ),
+            MAX_GAS,
+            0,
+        )
+    }
+
+    pub fn withdraw_voucher_pool(&self, user: &UserAccount) -> ExecutionResult {
+        user.function_call(
+            self.contract
+                .contract
+                .withdraw_voucher_pool(self.token.valid_account_id(), None),
+            CLAIM_GAS,
+            0,
+        )
+    }
+
+    pub fn get_voucher_pool_balance(&self, user: &UserAccount) -> Balance {
+        let balance: WrappedBalance =
+            self.near
+                .view_method_call(self.contract.contract.get_voucher_pool_balance(
+                    user.valid_account_id(),
+                    self.token.valid_account_id(),
+                ))
+                .unwrap_json();
+        balance.0
+    }
+
+    pub fn get_campaign(&self, campaign_index: CampaignIndex) -> Option<CampaignView> {
+        self.near
+            .view_method_call(self.contract.contract.get_campaign(campaign_index))
+            .unwrap_json()
+    }
+
+    pub fn claim(&self, user: &UserAccount) -> ExecutionResult {
+        user.function_call(self.contract.contract.claim(None), CLAIM_GAS, 0)
+    }
+
+    pub fn claim_with_storage_deposit(
+        &self,
+        user: &UserAccount,
+        storage_deposit: Balance,
+    ) -> ExecutionResult {
+        user.function_call(self.contract.contract.claim(None), MAX_GAS, storage_deposit)
+    }
+
+    pub fn claim_for(
+        &self,
+        user: &UserAccount,
+        account: &UserAccount,
+        fee_bps: Option<u32>,
+    ) -> ExecutionResult {
+        user.function_call(
+            self.contract
+                .contract
+                .claim_for(account.valid_account_id(), None, fee_bps),
+            CLAIM_GAS,
+            0,
+        )
+    }
+
+    pub fn claim_batch(
+        &self,
+        user: &UserAccount,
+        accounts: &[&UserAccount],
+        gas: Gas,
+    ) -> ExecutionResult {
+        user.function_call(
+            self.contract.contract.claim_batch(
+                accounts
+                    .iter()
+                    .map(|account| account.valid_account_id())
+                    .collect(),
+                self.token.account_id(),
+            ),
+            gas,
+            0,
+        )
+    }
+
+    pub fn set_claim_for_allowed(&self, user: &UserAccount, allowed: bool) -> ExecutionResult {
+        user.function_call(
+            self.contract.contract.set_claim_for_allowed(allowed),
+            DEFAULT_GAS,
+            1,
+        )
+    }
+
+    pub fn add_keeper(&self, keeper: &UserAccount) {
+        self.owner
+            .function_call(
+                self.contract.contract.add_keeper(keeper.valid_account_id()),
+                DEFAULT_GAS,
+                1,
+            )
+            .assert_success();
+    }
+
+    pub fn set_max_keeper_fee_bps(&self, max_keeper_fee_bps: u32) -> ExecutionResult {
+        self.owner.function_call(
+            self.contract
+                .contract
+                .set_max_keeper_fee_bps(max_keeper_fee_bps),
+            DEFAULT_GAS,
+            1,
+        )
+    }
+
+    pub fn claim_token(&self, user: &UserAccount, token_id: &str) -> ExecutionResult {
+        user.function_call(
+            self.contract.contract.claim(Some(token_id.to_string())),
+            CLAIM_GAS,
+            0,
+        )
+    }
+
+    pub fn claim_with_gas(&self, user: &UserAccount, gas: Gas) -> ExecutionResult {
+        user.function_call(self.contract.contract.claim(None), gas, 0)
+    }
+
+    pub fn get_near_locked_balance(&self) -> Balance {
+        let balance: WrappedBalance = self
+            .near
+            .view_method_call(self.contract.contract.get_near_locked_balance())
+            .unwrap_json();
+        balance.0
+    }
+
+    pub fn withdraw(&self, user: &UserAccount, amount: Option<Balance>) -> ExecutionResult {
+        user.function_call(
+            self.contract
+                .contract
+                .withdraw(self.token.account_id(), amount.map(|amount| amount.into())),
+            CLAIM_GAS,
+            0,
+        )
+    }
+
+    pub fn get_withdrawable(&self, user: &UserAccount) -> Balance {
+        let withdrawable: HashMap<String, WrappedBalance> = self
+            .near
             .view_method_call(
                 self.contract
                     .contract
-                    .get_account_lockups(user.valid_account_id()),
+                    .get_withdrawable(user.valid_account_id()),
+            )
+            .unwrap_json();
+        withdrawable
+            .get(TOKEN_ID)
+            .map(|balance| balance.0)
+            .unwrap_or_default()
+    }
+
+    pub fn set_rollback_failed_claims(&self, enabled: bool) {
+        self.owner
+            .function_call(
+                self.contract.contract.set_rollback_failed_claims(enabled),
+                DEFAULT_GAS,
+                1,
             )
+            .assert_success();
+    }
+
+    pub fn get_account_lockups(&self, user: &UserAccount) -> Vec<(LockupIndex, LockupView)> {
+        self.near
+            .view_method_call(self.contract.contract.get_account_lockups(
+                user.valid_account_id(),
+                None,
+                None,
+            ))
+            .unwrap_json()
+    }
+
+    pub fn get_account_lockups_paged(
+        &self,
+        user: &UserAccount,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<(LockupIndex, LockupView)> {
+        self.near
+            .view_method_call(self.contract.contract.get_account_lockups(
+                user.valid_account_id(),
+                from_index,
+                limit,
+            ))
             .unwrap_json()
     }
 
@@ -192,11 +892,30 @@ impl Env {
         lockup.unwrap()
     }
 
+    pub fn get_lockups_paged(
+        &self,
+        from_index: Option<LockupIndex>,
+        limit: Option<LockupIndex>,
+        filter: Option<LockupFilter>,
+    ) -> LockupsPage {
+        self.near
+            .view_method_call(
+                self.contract
+                    .contract
+                    .get_lockups_paged(from_index, limit, filter),
+            )
+            .unwrap_json()
+    }
+
     pub fn ft_balance_of(&self, user: &UserAccount) -> Balance {
+        self.ft_balance_of_token(&self.token.account_id, user)
+    }
+
+    pub fn ft_balance_of_token(&self, token_id: &str, user: &UserAccount) -> Balance {
         let balance: WrappedBalance = self
             .near
             .view(
-                self.token.account_id.clone(),
+                token_id.to_string(),
                 "ft_balance_of",
                 &json!({
                     "account_id": user.valid_account_id(),
@@ -208,6 +927,78 @@ impl Env {
         balance.0
     }
 
+    pub fn get_owner(&self) -> AccountId {
+        self.near
+            .view(
+                self.contract.user_account.account_id.clone(),
+                "get_owner",
+                &[],
+            )
+            .unwrap_json()
+    }
+
+    pub fn get_pending_owner(&self) -> Option<AccountId> {
+        self.near
+            .view_method_call(self.contract.contract.get_pending_owner())
+            .unwrap_json()
+    }
+
+    pub fn pause(&self, kind: PauseKind) -> ExecutionResult {
+        self.owner
+            .function_call(self.contract.contract.pause(kind), DEFAULT_GAS, 1)
+    }
+
+    pub fn unpause(&self, kind: PauseKind) -> ExecutionResult {
+        self.owner
+            .function_call(self.contract.contract.unpause(kind), DEFAULT_GAS, 1)
+    }
+
+    pub fn get_pause_state(&self) -> PauseState {
+        self.near
+            .view_method_call(self.contract.contract.get_pause_state())
+            .unwrap_json()
+    }
+
+    pub fn add_to_blacklist(
+        &self,
+        user: &UserAccount,
+        expires_at: Option<TimestampSec>,
+        freeze: bool,
+    ) -> ExecutionResult {
+        self.owner.function_call(
+            self.contract.contract.add_to_blacklist(
+                user.valid_account_id(),
+                Some("test".to_string()),
+                expires_at,
+                Some(freeze),
+            ),
+            DEFAULT_GAS,
+            1,
+        )
+    }
+
+    pub fn storage_available(&self, user: &UserAccount) -> Option<Balance> {
+        let storage_balance: Option<serde_json::Value> = self
+            .near
+            .view(
+                self.contract.user_account.account_id.clone(),
+                "storage_balance_of",
+                &json!({
+                    "account_id": user.valid_account_id(),
+                })
+                .to_string()
+                .into_bytes(),
+            )
+            .unwrap_json();
+        storage_balance.map(|storage_balance| {
+            storage_balance["available"]
+                .as_str()
+                .unwrap()
+                .parse()
+                .unwrap()
+        })
+    }
+
     pub fn set_time_sec(&self, timestamp_sec: TimestampSec) {
         self.near.borrow_runtime_mut().cur_block.block_timestamp = to_nano(timestamp_sec);
     }